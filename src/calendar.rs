@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use gtfs_structures::{Exception, Gtfs};
+use serde::{Deserialize, Serialize};
+use crate::model::ServiceIdx;
+
+/// Days of the week a service runs on between two dates, from calendar.txt
+#[derive(Serialize, Deserialize)]
+struct ServicePeriod {
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    /// Monday first
+    weekdays: [bool; 7],
+}
+
+/// Time of a trip as GTFS models it, seconds since noon minus 12 hours of its service date.
+/// The seconds exceed a day for trips running past midnight
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ServiceTime {
+    pub date: NaiveDate,
+    pub seconds: u32,
+}
+
+/// Answers which services run on a given date,
+/// according to calendar.txt and the exceptions from calendar_dates.txt
+#[derive(Serialize, Deserialize)]
+pub struct ServiceCalendar {
+    service_ids: Vec<String>,
+    service_indices: HashMap<String, ServiceIdx>,
+    periods: Vec<Option<ServicePeriod>>,
+    /// (service, date) -> whether the service was added (`true`) or removed on the date
+    exceptions: HashMap<(ServiceIdx, NaiveDate), bool>,
+    /// Timezone of the first agency in the feed, all GTFS times are local to it
+    pub timezone: Tz,
+}
+
+impl ServiceCalendar {
+    pub fn new(gtfs: &Gtfs) -> Self {
+        // Services referenced only by trips never run, but still get an index
+        let mut service_ids: Vec<String> = gtfs.calendar.keys()
+            .chain(gtfs.calendar_dates.keys())
+            .chain(gtfs.trips.values().map(|trip| &trip.service_id))
+            .cloned()
+            .collect();
+        service_ids.sort();
+        service_ids.dedup();
+
+        let service_indices: HashMap<String, ServiceIdx> = service_ids
+            .iter()
+            .enumerate()
+            .map(|(service_idx, service_id)| (service_id.clone(), service_idx as ServiceIdx))
+            .collect();
+
+        let periods = service_ids
+            .iter()
+            .map(|service_id| gtfs.calendar.get(service_id).map(|c| ServicePeriod {
+                start_date: c.start_date,
+                end_date: c.end_date,
+                weekdays: [c.monday, c.tuesday, c.wednesday, c.thursday, c.friday, c.saturday, c.sunday],
+            }))
+            .collect();
+
+        let exceptions = gtfs.calendar_dates
+            .iter()
+            .flat_map(|(service_id, dates)| {
+                let service_idx = service_indices[service_id];
+                dates.iter().map(move |d| ((service_idx, d.date), d.exception_type == Exception::Added))
+            })
+            .collect();
+
+        let timezone = gtfs.agencies
+            .first()
+            .and_then(|agency| agency.timezone.parse::<Tz>().ok())
+            .unwrap_or(Tz::UTC);
+
+        ServiceCalendar { service_ids, service_indices, periods, exceptions, timezone }
+    }
+
+    pub fn get_service_idx(&self, service_id: &str) -> Option<ServiceIdx> {
+        self.service_indices.get(service_id).copied()
+    }
+
+    pub fn runs_on(&self, service_idx: ServiceIdx, date: NaiveDate) -> bool {
+        match self.exceptions.get(&(service_idx, date)) {
+            Some(&added) => added,
+            None => self.periods[service_idx as usize].as_ref().is_some_and(|p| {
+                p.start_date <= date && date <= p.end_date && p.weekdays[date.weekday().num_days_from_monday() as usize]
+            }),
+        }
+    }
+
+    /// Returns a flag for every service, telling whether it runs on the date
+    pub fn active_services(&self, date: NaiveDate) -> Vec<bool> {
+        (0..self.service_ids.len() as ServiceIdx)
+            .map(|service_idx| self.runs_on(service_idx, date))
+            .collect()
+    }
+
+    pub fn now(&self) -> DateTime<Tz> {
+        Utc::now().with_timezone(&self.timezone)
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        self.now().date_naive()
+    }
+
+    /// Seconds since midnight in the feed timezone
+    pub fn seconds_since_midnight(&self) -> u32 {
+        let now = self.now();
+        now.hour() * 3600 + now.minute() * 60 + now.second()
+    }
+
+    /// POSIX time GTFS times of the service date are counted from. GTFS defines it as
+    /// noon minus 12 hours, which differs from midnight on daylight saving time changes
+    pub fn service_day_start(&self, date: NaiveDate) -> i64 {
+        self.local_timestamp(date.and_hms_opt(12, 0, 0).unwrap()) - 12 * 3600
+    }
+
+    /// Converts seconds since midnight of the date in the feed timezone into seconds since the start
+    /// of the service day before it, which is where the times of a search count from. When the clocks
+    /// move back the service day of the date starts at 01:00, earlier times still belong to the day before
+    pub fn search_seconds(&self, date: NaiveDate, seconds_since_midnight: u32) -> u32 {
+        let local = date.and_hms_opt(0, 0, 0).unwrap() + Duration::seconds(i64::from(seconds_since_midnight));
+        (self.local_timestamp(local) - self.service_day_start(date.pred_opt().unwrap())) as u32
+    }
+
+    /// POSIX time of a wall clock time in the feed timezone. Repeated times take the earlier one, times skipped
+    /// when the clocks move forward are shifted forward by the gap, keeping the offset from before it
+    fn local_timestamp(&self, local: NaiveDateTime) -> i64 {
+        match self.timezone.from_local_datetime(&local) {
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time.timestamp(),
+            LocalResult::None => {
+                let offset_before = self.timezone.offset_from_utc_datetime(&(local - Duration::days(1))).fix();
+                local.and_utc().timestamp() - i64::from(offset_before.local_minus_utc())
+            },
+        }
+    }
+
+    /// Seconds between the starts of the service day before the date and of the date itself
+    pub fn previous_day_length(&self, date: NaiveDate) -> u32 {
+        (self.service_day_start(date) - self.service_day_start(date.pred_opt().unwrap())) as u32
+    }
+
+    pub fn timestamp(&self, time: ServiceTime) -> i64 {
+        self.service_day_start(time.date) + i64::from(time.seconds)
+    }
+
+    /// Wall clock time in the feed timezone, formatted as HH:MM
+    pub fn format_time(&self, time: ServiceTime) -> String {
+        self.timezone
+            .timestamp_opt(self.timestamp(time), 0)
+            .single()
+            .map_or_else(String::new, |time| time.format("%H:%M").to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use crate::test_feed;
+    use super::{ServiceCalendar, ServiceTime};
+
+    /// Weekday service WD, which does not run on 2026-10-28, and service HOL running only on 2026-10-24
+    fn calendar() -> ServiceCalendar {
+        ServiceCalendar::new(&test_feed::load(&[
+            ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\nS,Stop,48.1,17.1\n"),
+            ("routes.txt", "route_id,route_short_name,route_type\nR,1,3\n"),
+            ("trips.txt", "route_id,service_id,trip_id\n"),
+            ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n"),
+            ("calendar_dates.txt", "service_id,date,exception_type\nWD,20261028,2\nHOL,20261024,1\n"),
+        ]))
+    }
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, month, day).unwrap()
+    }
+
+    #[test]
+    fn applies_added_and_removed_dates() {
+        let calendar = calendar();
+        let (weekdays, holiday) = (calendar.get_service_idx("WD").unwrap(), calendar.get_service_idx("HOL").unwrap());
+
+        assert!(calendar.runs_on(weekdays, date(10, 27)));
+        assert!(!calendar.runs_on(weekdays, date(10, 28)));
+        assert!(!calendar.runs_on(weekdays, date(10, 24)));
+        assert!(calendar.runs_on(holiday, date(10, 24)));
+        assert!(!calendar.runs_on(holiday, date(10, 25)));
+        assert_eq!(calendar.active_services(date(10, 24)), [true, false]);
+    }
+
+    #[test]
+    fn counts_service_days_from_noon_on_daylight_saving_time_changes() {
+        let calendar = calendar();
+
+        // The clocks move forward on 2026-03-29 and back on 2026-10-25, so the days before are 23 and 25 hours long
+        assert_eq!(calendar.previous_day_length(date(3, 29)), 23 * 3600);
+        assert_eq!(calendar.previous_day_length(date(3, 30)), 24 * 3600);
+        assert_eq!(calendar.previous_day_length(date(10, 25)), 25 * 3600);
+        assert_eq!(calendar.previous_day_length(date(10, 26)), 24 * 3600);
+
+        // Wall clock times after the change keep their GTFS times, which count from noon minus 12 hours
+        for (month, day) in [(3, 29), (10, 25)] {
+            let offset = calendar.previous_day_length(date(month, day));
+            assert_eq!(calendar.search_seconds(date(month, day), 8 * 3600), offset + 8 * 3600);
+            assert_eq!(calendar.format_time(ServiceTime { date: date(month, day), seconds: 8 * 3600 }), "08:00");
+        }
+
+        // The service day starts at 23:00 of the day before when the clocks move forward, and at 01:00 when they move back
+        assert_eq!(calendar.format_time(ServiceTime { date: date(3, 29), seconds: 0 }), "23:00");
+        assert_eq!(calendar.format_time(ServiceTime { date: date(10, 25), seconds: 0 }), "01:00");
+    }
+
+    #[test]
+    fn keeps_times_before_the_start_of_the_service_day() {
+        let calendar = calendar();
+
+        // 00:30 of 2026-10-25 comes half an hour before its service day starts, at 24:30 of the day before
+        let seconds = calendar.search_seconds(date(10, 25), 1800);
+        assert_eq!(seconds, 24 * 3600 + 1800);
+        assert!(seconds < calendar.previous_day_length(date(10, 25)));
+        assert_eq!(calendar.format_time(ServiceTime { date: date(10, 24), seconds }), "00:30");
+    }
+
+    #[test]
+    fn shifts_skipped_times_forward_by_the_gap() {
+        let calendar = calendar();
+
+        // 02:30 does not exist on 2026-03-29, it is taken as 03:30 right after the clocks moved forward
+        let offset = calendar.previous_day_length(date(3, 29));
+        let skipped = calendar.search_seconds(date(3, 29), 2 * 3600 + 1800) - offset;
+        assert_eq!(skipped, 3 * 3600 + 1800);
+        assert_eq!(calendar.format_time(ServiceTime { date: date(3, 29), seconds: skipped }), "03:30");
+        // Before the gap the times count from 23:00 of the day before
+        assert_eq!(calendar.search_seconds(date(3, 29), 3600 + 1800), offset + 2 * 3600 + 1800);
+
+        // The repeated 02:30 of 2026-10-25 is the first one, before the clocks moved back
+        let offset = calendar.previous_day_length(date(10, 25));
+        assert_eq!(calendar.search_seconds(date(10, 25), 2 * 3600 + 1800), offset + 3600 + 1800);
+    }
+}
@@ -0,0 +1,509 @@
+use std::{collections::HashMap, fs, io::{Cursor, Read}, path::PathBuf, sync::Arc, thread};
+use clap::{Parser, Subcommand};
+use crc32fast::Hasher;
+use gtfs_structures::{Gtfs, RouteType, StopTransfer, TransferType};
+use serde::{de::DeserializeOwned, Deserialize};
+use crate::{model::Mode, routing::{TransferOptions, WalkingOptions}};
+
+const DEFAULT_GTFS_URL: &str = "https://www.arcgis.com/sharing/rest/content/items/aba12fd2cbac4843bc7406151bc66106/data";
+const DEFAULT_HOST: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 8000;
+const DEFAULT_MAX_TRANSFERS: usize = 4;
+const DEFAULT_WALKING_RADIUS: f64 = 500.0;
+const DEFAULT_WALKING_SPEED: f64 = 1.2;
+const DEFAULT_MIN_CHANGE_TIME: u32 = 60;
+const DEFAULT_REALTIME_INTERVAL: u64 = 30;
+
+/// Command line flags. Every flag can also be provided through
+/// the environment variable listed next to it
+#[derive(Parser)]
+#[command(name = "bimhd", version, about = "REST API over GTFS public transport data")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to an optional TOML configuration file
+    #[arg(long, env = "BIMHD_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Local GTFS zip archive or directory
+    #[arg(long, env = "BIMHD_GTFS_PATH", global = true)]
+    pub gtfs_path: Option<PathBuf>,
+
+    /// Remote GTFS zip archive
+    #[arg(long, env = "BIMHD_GTFS_URL", global = true)]
+    pub gtfs_url: Option<String>,
+
+    /// Index snapshot loaded on start instead of building the index, rebuilt and rewritten when the feed changes
+    #[arg(long, env = "BIMHD_INDEX_PATH")]
+    pub index_path: Option<PathBuf>,
+
+    /// Address the server binds to
+    #[arg(long, env = "BIMHD_HOST")]
+    pub host: Option<String>,
+
+    /// Port the server listens on
+    #[arg(long, env = "BIMHD_PORT")]
+    pub port: Option<u16>,
+
+    /// Maximum number of transfers of a planned journey, unless the request asks for a different one
+    #[arg(long, env = "BIMHD_MAX_TRANSFERS")]
+    pub max_transfers: Option<usize>,
+
+    /// Maximum distance in meters between two platforms connected by a footpath
+    #[arg(long, env = "BIMHD_WALKING_RADIUS", global = true)]
+    pub walking_radius: Option<f64>,
+
+    /// Walking speed in meters per second
+    #[arg(long, env = "BIMHD_WALKING_SPEED", global = true)]
+    pub walking_speed: Option<f64>,
+
+    /// Minimum seconds between leaving one vehicle and boarding another one, unless transfers.txt
+    /// or the config file sets a different one for the stop or the mode
+    #[arg(long, env = "BIMHD_MIN_CHANGE_TIME", global = true)]
+    pub min_change_time: Option<u32>,
+
+    /// Number of threads handling requests, defaults to the number of CPU cores
+    #[arg(long, env = "BIMHD_WORKERS")]
+    pub workers: Option<usize>,
+
+    /// Local GTFS-Realtime TripUpdates feed
+    #[arg(long, env = "BIMHD_TRIP_UPDATES_PATH")]
+    pub trip_updates_path: Option<PathBuf>,
+
+    /// Remote GTFS-Realtime TripUpdates feed
+    #[arg(long, env = "BIMHD_TRIP_UPDATES_URL")]
+    pub trip_updates_url: Option<String>,
+
+    /// Local GTFS-Realtime VehiclePositions feed
+    #[arg(long, env = "BIMHD_VEHICLE_POSITIONS_PATH")]
+    pub vehicle_positions_path: Option<PathBuf>,
+
+    /// Remote GTFS-Realtime VehiclePositions feed
+    #[arg(long, env = "BIMHD_VEHICLE_POSITIONS_URL")]
+    pub vehicle_positions_url: Option<String>,
+
+    /// Local GTFS-Realtime ServiceAlerts feed
+    #[arg(long, env = "BIMHD_SERVICE_ALERTS_PATH")]
+    pub service_alerts_path: Option<PathBuf>,
+
+    /// Remote GTFS-Realtime ServiceAlerts feed
+    #[arg(long, env = "BIMHD_SERVICE_ALERTS_URL")]
+    pub service_alerts_url: Option<String>,
+
+    /// Seconds between two reads of the GTFS-Realtime feeds
+    #[arg(long, env = "BIMHD_REALTIME_INTERVAL")]
+    pub realtime_interval: Option<u64>,
+
+    /// Bearer token required by the admin endpoints, without one they only accept requests from loopback
+    #[arg(long, env = "BIMHD_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Builds the index and writes its snapshot without starting the server
+    BuildIndex {
+        /// Local GTFS zip archive or directory, overrides the configured feed
+        #[arg(long)]
+        gtfs: Option<PathBuf>,
+
+        /// Where to write the snapshot
+        #[arg(long)]
+        out: PathBuf,
+    },
+}
+
+/// Contents of the TOML configuration file, all keys are optional
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    gtfs_path: Option<PathBuf>,
+    gtfs_url: Option<String>,
+    index_path: Option<PathBuf>,
+    host: Option<String>,
+    port: Option<u16>,
+    max_transfers: Option<usize>,
+    walking_radius: Option<f64>,
+    walking_speed: Option<f64>,
+    min_change_time: Option<u32>,
+    /// Mode name -> minimum change time at the platforms it serves
+    mode_change_times: HashMap<String, u32>,
+    /// Stop name -> minimum change time at its platforms
+    station_change_times: HashMap<String, u32>,
+    workers: Option<usize>,
+    trip_updates_path: Option<PathBuf>,
+    trip_updates_url: Option<String>,
+    vehicle_positions_path: Option<PathBuf>,
+    vehicle_positions_url: Option<String>,
+    service_alerts_path: Option<PathBuf>,
+    service_alerts_url: Option<String>,
+    realtime_interval: Option<u64>,
+    admin_token: Option<String>,
+}
+
+#[derive(Clone)]
+pub enum FeedSource {
+    Path(PathBuf),
+    Url(String),
+}
+
+impl FeedSource {
+    /// Picks the local path over the URL, like for the static feed
+    fn from_options(path: Option<PathBuf>, url: Option<String>) -> Option<Self> {
+        path.map(FeedSource::Path).or(url.map(FeedSource::Url))
+    }
+
+    /// Reads the raw feed, a remote archive is downloaded only once
+    /// so its checksum and the parsed feed come from the same bytes
+    pub fn fetch(&self) -> Result<FeedContents, String> {
+        match self {
+            FeedSource::Path(path) if path.is_dir() => Ok(FeedContents::Directory(path.clone())),
+            _ => self.read().map(FeedContents::Archive),
+        }
+    }
+
+    /// Reads the whole file, or downloads it
+    pub fn read(&self) -> Result<Vec<u8>, String> {
+        match self {
+            FeedSource::Path(path) => fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e)),
+            FeedSource::Url(url) => reqwest::blocking::get(url.as_str())
+                .and_then(|response| response.error_for_status())
+                .and_then(|response| response.bytes())
+                .map(|bytes| bytes.to_vec())
+                .map_err(|e| format!("Could not download {}: {}", url, e)),
+        }
+    }
+}
+
+/// Columns of routes.txt needed to restore the extended route types. The type is parsed
+/// separately, so a malformed one only skips its route
+#[derive(Deserialize)]
+struct RouteTypeRecord {
+    route_id: String,
+    route_type: String,
+}
+
+/// Columns of transfers.txt, with the trips and routes a transfer can be restricted to
+#[derive(Deserialize)]
+struct TransferRecord {
+    from_stop_id: String,
+    to_stop_id: String,
+    transfer_type: TransferType,
+    min_transfer_time: Option<u32>,
+    #[serde(default)]
+    from_route_id: Option<String>,
+    #[serde(default)]
+    to_route_id: Option<String>,
+    #[serde(default)]
+    from_trip_id: Option<String>,
+    #[serde(default)]
+    to_trip_id: Option<String>,
+}
+
+pub enum FeedContents {
+    /// Zip archive
+    Archive(Vec<u8>),
+    /// Directory with the extracted txt files
+    Directory(PathBuf),
+}
+
+impl FeedContents {
+    /// CRC32 of the archive, or of the names and contents of the files in the directory
+    pub fn checksum(&self) -> Result<u32, String> {
+        let mut hasher = Hasher::new();
+
+        match self {
+            FeedContents::Archive(bytes) => hasher.update(bytes),
+            FeedContents::Directory(path) => {
+                let read_error = |e: std::io::Error| format!("Could not read {}: {}", path.display(), e);
+                let mut files: Vec<PathBuf> = fs::read_dir(path)
+                    .map_err(read_error)?
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|file| file.is_file())
+                    .collect();
+                files.sort();
+
+                for file in files {
+                    hasher.update(file.file_name().unwrap_or_default().as_encoded_bytes());
+                    hasher.update(&fs::read(&file).map_err(read_error)?);
+                }
+            },
+        }
+
+        Ok(hasher.finalize())
+    }
+
+    /// Parses the feed. gtfs-structures folds the extended route types into the basic ones,
+    /// so they are read again from routes.txt and kept as they are. It also drops the trips and routes
+    /// of transfers.txt, so a transfer restricted to some of them would apply to every vehicle,
+    /// the stops keep only the transfers without such a restriction
+    pub fn parse(&self) -> Result<Gtfs, String> {
+        let mut gtfs = match self {
+            FeedContents::Archive(bytes) => Gtfs::from_reader(Cursor::new(bytes)),
+            FeedContents::Directory(path) => Gtfs::from_path(path),
+        }.map_err(|e| e.to_string())?;
+
+        let routes = self.read_file("routes.txt")?.ok_or("Could not read routes.txt: missing from the feed")?;
+        for record in records::<RouteTypeRecord>(&routes, "routes.txt") {
+            let Ok(route_type) = record.route_type.parse::<i16>() else {
+                eprintln!("[!] Skipping invalid route_type {} of route {} in routes.txt", record.route_type, record.route_id);
+                continue;
+            };
+            if let Some(route) = gtfs.routes.get_mut(&record.route_id).filter(|_| route_type >= 100) {
+                route.route_type = RouteType::Other(route_type);
+            }
+        }
+
+        if let Some(transfers) = self.read_file("transfers.txt")? {
+            let mut stop_transfers: HashMap<String, Vec<StopTransfer>> = HashMap::new();
+            for record in records::<TransferRecord>(&transfers, "transfers.txt") {
+                let restrictions = [&record.from_route_id, &record.to_route_id, &record.from_trip_id, &record.to_trip_id];
+                if restrictions.iter().all(|restriction| restriction.is_none()) {
+                    stop_transfers.entry(record.from_stop_id).or_default().push(StopTransfer {
+                        to_stop_id: record.to_stop_id,
+                        transfer_type: record.transfer_type,
+                        min_transfer_time: record.min_transfer_time,
+                    });
+                }
+            }
+
+            gtfs.stops.iter_mut().filter(|(_, stop)| !stop.transfers.is_empty()).for_each(|(stop_id, stop)| {
+                Arc::make_mut(stop).transfers = stop_transfers.remove(stop_id).unwrap_or_default();
+            });
+        }
+
+        Ok(gtfs)
+    }
+
+    /// Contents of a file of the feed, which can be in a folder of the archive. `None` when the feed has no such file
+    fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let read_error = |e: &dyn std::fmt::Display| format!("Could not read {}: {}", name, e);
+
+        match self {
+            FeedContents::Archive(bytes) => {
+                let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(|e| read_error(&e))?;
+                let Some(path) = archive.file_names().find(|path| path.rsplit('/').next() == Some(name)).map(str::to_string) else {
+                    return Ok(None);
+                };
+
+                let mut contents = Vec::new();
+                archive
+                    .by_name(&path)
+                    .map_err(|e| read_error(&e))?
+                    .read_to_end(&mut contents)
+                    .map_err(|e| read_error(&e))?;
+                Ok(Some(contents))
+            },
+            FeedContents::Directory(path) if !path.join(name).exists() => Ok(None),
+            FeedContents::Directory(path) => fs::read(path.join(name)).map(Some).map_err(|e| read_error(&e)),
+        }
+    }
+}
+
+/// Rows of a csv file of the feed, read as leniently as gtfs-structures does: a byte order mark is skipped,
+/// fields are trimmed and rows can have fewer or more fields than the header. Malformed rows are skipped with a warning
+fn records<'a, T: DeserializeOwned + 'a>(contents: &'a [u8], name: &'a str) -> impl Iterator<Item = T> + 'a {
+    let contents = contents.strip_prefix(b"\xef\xbb\xbf").unwrap_or(contents);
+    csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(contents)
+        .into_deserialize::<T>()
+        .filter_map(move |record| {
+            record.inspect_err(|e| eprintln!("[!] Skipping invalid row of {}: {}", name, e)).ok()
+        })
+}
+
+impl std::fmt::Display for FeedSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FeedSource::Path(path) => write!(f, "{}", path.display()),
+            FeedSource::Url(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+pub struct Config {
+    pub command: Option<Command>,
+    pub feed: FeedSource,
+    pub index_path: Option<PathBuf>,
+    pub host: String,
+    pub port: u16,
+    pub max_transfers: usize,
+    pub walking: WalkingOptions,
+    pub transfers: TransferOptions,
+    pub workers: usize,
+    /// GTFS-Realtime TripUpdates, the delays are applied to departures and planned journeys
+    pub trip_updates: Option<FeedSource>,
+    /// GTFS-Realtime VehiclePositions, listed by the vehicles endpoint
+    pub vehicle_positions: Option<FeedSource>,
+    /// GTFS-Realtime ServiceAlerts, attached to the stops, departures and journeys they concern
+    pub service_alerts: Option<FeedSource>,
+    pub realtime_interval: u64,
+    /// Bearer token of the admin endpoints, `None` limits them to loopback
+    pub admin_token: Option<String>,
+}
+
+impl Config {
+    /// Resolves the configuration with the following precedence:
+    /// command line flags, environment variables, config file, defaults.
+    /// A local path always wins over a URL coming from the same source
+    pub fn load() -> Result<Self, String> {
+        let cli = Cli::parse();
+
+        let file = match &cli.config {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+                toml::from_str::<FileConfig>(&contents)
+                    .map_err(|e| format!("Could not parse config file {}: {}", path.display(), e))?
+            },
+            None => FileConfig::default(),
+        };
+
+        let build_index_gtfs = match &cli.command {
+            Some(Command::BuildIndex { gtfs, .. }) => gtfs.clone(),
+            None => None,
+        };
+
+        let feed = if let Some(path) = build_index_gtfs {
+            FeedSource::Path(path)
+        } else if let Some(path) = cli.gtfs_path {
+            FeedSource::Path(path)
+        } else if let Some(url) = cli.gtfs_url {
+            FeedSource::Url(url)
+        } else if let Some(path) = file.gtfs_path {
+            FeedSource::Path(path)
+        } else if let Some(url) = file.gtfs_url {
+            FeedSource::Url(url)
+        } else {
+            FeedSource::Url(DEFAULT_GTFS_URL.to_string())
+        };
+
+        let walking = WalkingOptions {
+            radius: cli.walking_radius.or(file.walking_radius).unwrap_or(DEFAULT_WALKING_RADIUS),
+            speed: cli.walking_speed.or(file.walking_speed).unwrap_or(DEFAULT_WALKING_SPEED),
+        };
+
+        if walking.speed <= 0.0 || walking.radius < 0.0 {
+            return Err("Walking speed must be positive and walking radius must not be negative".to_string());
+        }
+
+        let mut mode_change_times = [None; Mode::ALL.len()];
+        for (name, &change_time) in &file.mode_change_times {
+            let mode = Mode::from_name(name).ok_or_else(|| format!("Unknown mode {} in mode_change_times", name))?;
+            mode_change_times[mode as usize] = Some(change_time);
+        }
+
+        let transfers = TransferOptions {
+            min_change_time: cli.min_change_time.or(file.min_change_time).unwrap_or(DEFAULT_MIN_CHANGE_TIME),
+            mode_change_times,
+            station_change_times: file.station_change_times,
+        };
+
+        let workers = cli.workers
+            .or(file.workers)
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+
+        if workers == 0 {
+            return Err("Number of workers must be positive".to_string());
+        }
+
+        let realtime_interval = cli.realtime_interval.or(file.realtime_interval).unwrap_or(DEFAULT_REALTIME_INTERVAL);
+
+        if realtime_interval == 0 {
+            return Err("Realtime interval must be positive".to_string());
+        }
+
+        Ok(Config {
+            command: cli.command,
+            feed,
+            index_path: cli.index_path.or(file.index_path),
+            host: cli.host.or(file.host).unwrap_or(DEFAULT_HOST.to_string()),
+            port: cli.port.or(file.port).unwrap_or(DEFAULT_PORT),
+            max_transfers: cli.max_transfers.or(file.max_transfers).unwrap_or(DEFAULT_MAX_TRANSFERS),
+            walking,
+            transfers,
+            workers,
+            trip_updates: FeedSource::from_options(cli.trip_updates_path, cli.trip_updates_url)
+                .or(FeedSource::from_options(file.trip_updates_path, file.trip_updates_url)),
+            vehicle_positions: FeedSource::from_options(cli.vehicle_positions_path, cli.vehicle_positions_url)
+                .or(FeedSource::from_options(file.vehicle_positions_path, file.vehicle_positions_url)),
+            service_alerts: FeedSource::from_options(cli.service_alerts_path, cli.service_alerts_url)
+                .or(FeedSource::from_options(file.service_alerts_path, file.service_alerts_url)),
+            realtime_interval,
+            admin_token: cli.admin_token.or(file.admin_token).filter(|token| !token.is_empty()),
+        })
+    }
+
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::{Cursor, Write}};
+    use crate::{routing::{TransferOptions, WalkingOptions}, test_feed, transit_index::TransitIndex};
+    use gtfs_structures::RouteType;
+    use super::{records, FeedContents, RouteTypeRecord};
+
+    /// Modes of the routes of a feed with basic and extended route types, read from a directory and from an archive
+    #[test]
+    fn extended_route_types_keep_their_modes() {
+        let path = test_feed::write(&[
+            ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\nS,Stop,48.1,17.1\n"),
+            ("routes.txt", "route_id,route_short_name,route_type\n\
+                BUS,1,3\nTROLLEY,2,11\nEXT_TROLLEY,3,800\nEXT_TRAM,4,900\nEXT_BUS,5,700\n\
+                EXT_RAIL,6,109\nEXT_METRO,7,401\nEXT_MONORAIL,8,405\nEXT_LIFT,9,1300\nEXT_OTHER,10,1700\n"),
+            ("trips.txt", "route_id,service_id,trip_id\n"),
+            ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n"),
+        ]);
+
+        let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for entry in fs::read_dir(&path).unwrap() {
+            let file = entry.unwrap().path();
+            archive.start_file(format!("feed/{}", file.file_name().unwrap().to_str().unwrap()), Default::default()).unwrap();
+            archive.write_all(&fs::read(&file).unwrap()).unwrap();
+        }
+        let archive = archive.finish().unwrap().into_inner();
+
+        let transfers = TransferOptions { min_change_time: 60, mode_change_times: Default::default(), station_change_times: Default::default() };
+        for feed in [FeedContents::Directory(path.clone()), FeedContents::Archive(archive)] {
+            let index = TransitIndex::new(&feed.parse().unwrap(), WalkingOptions { radius: 300.0, speed: 1.4 }, &transfers);
+            let mode = |route_id: &str| index.routes.iter().find(|route| route.id == route_id).map(|route| route.mode.name());
+
+            for (route_id, expected) in [
+                ("BUS", "bus"), ("TROLLEY", "trolleybus"), ("EXT_TROLLEY", "trolleybus"), ("EXT_TRAM", "tram"), ("EXT_BUS", "bus"),
+                ("EXT_RAIL", "rail"), ("EXT_METRO", "subway"), ("EXT_MONORAIL", "monorail"), ("EXT_LIFT", "aerial_lift"), ("EXT_OTHER", "other"),
+            ] {
+                assert_eq!(mode(route_id), Some(expected), "mode of {}", route_id);
+            }
+        }
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    /// A byte order mark does not hide the first column, and a malformed row skips only itself
+    #[test]
+    fn reads_route_types_as_leniently_as_the_feed() {
+        let feed = FeedContents::Directory(test_feed::write(&[
+            ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\nS,Stop,48.1,17.1\n"),
+            ("routes.txt", "\u{feff}route_id,route_short_name,route_type,route_color\nEXT_BUS,1,700,FF0000\nEXT_TRAM,2,900,\n"),
+            ("trips.txt", "route_id,service_id,trip_id\n"),
+            ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n"),
+        ]));
+        let gtfs = feed.parse().unwrap();
+        let route_type = |route_id: &str| gtfs.routes.get(route_id).map(|route| route.route_type);
+        assert_eq!(route_type("EXT_BUS"), Some(RouteType::Other(700)));
+        assert_eq!(route_type("EXT_TRAM"), Some(RouteType::Other(900)));
+
+        // The route type of B is skipped only when parsed, C misses it altogether
+        let routes = records::<RouteTypeRecord>(b"route_id,route_type\nA,3\nB,bus\nC\nD,901\n", "routes.txt");
+        assert_eq!(routes.map(|record| record.route_id).collect::<Vec<_>>(), ["A", "B", "D"]);
+
+        let FeedContents::Directory(path) = feed else { unreachable!() };
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
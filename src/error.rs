@@ -0,0 +1,88 @@
+use std::{fmt, io::Cursor};
+use tiny_http::{Header, Response};
+
+/// Errors returned to API clients. Every variant maps to an HTTP status
+/// and a machine-readable code, which is sent along with the message
+#[derive(Debug)]
+pub enum ApiError {
+    /// Required query parameter is not present
+    MissingParameter(&'static str),
+    /// Query parameter is present, but could not be parsed
+    InvalidParameter(&'static str),
+    /// Query parameters which can not be given together
+    ConflictingParameters(&'static str, &'static str),
+    /// Request target is not a valid URL path
+    InvalidUrl(String),
+    /// No stop name is similar to the requested one
+    StopNotFound(String),
+    NoRouteFound,
+    /// Unknown endpoint
+    NotFound(String),
+    /// Endpoint does not support the request method
+    MethodNotAllowed(String),
+    /// Admin endpoint requested without the admin token, or from another host when no token is configured
+    Forbidden,
+    ReloadInProgress,
+    /// Handling the request panicked
+    Internal,
+}
+
+impl ApiError {
+    pub fn status_code(&self) -> u16 {
+        match self {
+            ApiError::MissingParameter(_) | ApiError::InvalidParameter(_) | ApiError::ConflictingParameters(..) | ApiError::InvalidUrl(_) => 400,
+            ApiError::StopNotFound(_) | ApiError::NoRouteFound | ApiError::NotFound(_) => 404,
+            ApiError::Forbidden => 403,
+            ApiError::MethodNotAllowed(_) => 405,
+            ApiError::ReloadInProgress => 409,
+            ApiError::Internal => 500,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::MissingParameter(_) => "missing_parameter",
+            ApiError::InvalidParameter(_) => "invalid_parameter",
+            ApiError::ConflictingParameters(..) => "conflicting_parameters",
+            ApiError::InvalidUrl(_) => "invalid_url",
+            ApiError::StopNotFound(_) => "stop_not_found",
+            ApiError::NoRouteFound => "no_route_found",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::MethodNotAllowed(_) => "method_not_allowed",
+            ApiError::Forbidden => "forbidden",
+            ApiError::ReloadInProgress => "reload_in_progress",
+            ApiError::Internal => "internal_error",
+        }
+    }
+
+    pub fn to_response(&self) -> Response<Cursor<Vec<u8>>> {
+        let body = serde_json::json!({
+            "error": self.to_string(),
+            "code": self.code(),
+        });
+
+        Response::from_string(body.to_string())
+            .with_status_code(self.status_code())
+            .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
+            .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
+            .with_header(Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, POST, PUT, DELETE, OPTIONS"[..]).unwrap())
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::MissingParameter(name) => write!(f, "Missing {} query parameter", name),
+            ApiError::InvalidParameter(name) => write!(f, "Invalid {} query parameter", name),
+            ApiError::ConflictingParameters(first, second) => write!(f, "The {} and {} query parameters can not be combined", first, second),
+            ApiError::InvalidUrl(url) => write!(f, "Invalid request target: {}", url),
+            ApiError::StopNotFound(name) => write!(f, "Stop not found: {}", name),
+            ApiError::NoRouteFound => write!(f, "No route found"),
+            ApiError::NotFound(path) => write!(f, "Not found: {}", path),
+            ApiError::MethodNotAllowed(method) => write!(f, "Method not allowed: {}", method),
+            ApiError::Forbidden => write!(f, "Forbidden"),
+            ApiError::ReloadInProgress => write!(f, "Reload already in progress"),
+            ApiError::Internal => write!(f, "Internal server error"),
+        }
+    }
+}
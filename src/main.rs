@@ -1,233 +1,601 @@
+mod calendar;
+mod config;
+mod error;
+mod model;
+mod realtime;
+mod reload;
+mod routing;
+mod search;
+mod snapshot;
+mod spatial_index;
+#[cfg(test)]
+mod test_feed;
 mod transit_index;
 mod util;
 
-use std::collections::{BTreeSet, HashMap};
-use gtfs_structures::{Gtfs, Id};
-use serde_json::to_string;
-use tiny_http::{Header, Response, Server};
-use transit_index::TransitIndex;
-use util::{format_u32_time, format_seconds_to_minutes};
+use std::{collections::{BTreeSet, HashMap}, io::Cursor, panic::{self, AssertUnwindSafe}, path::Path, sync::Arc, thread};
+use calendar::ServiceTime;
+use config::{Command, Config, FeedSource};
+use error::ApiError;
+use model::{ModeFilter, StopIdx};
+use routing::{Algorithm, JourneyOptions, Leg, Place, SortBy, TimeConstraint};
+use serde_json::{to_string, Value};
+use realtime::{InformedEntity, Realtime, ServiceAlert, VehicleFilter};
+use reload::SharedIndex;
+use tiny_http::{Header, Method, Request, Response, Server};
+use transit_index::{StopPlatforms, TransitIndex};
+use util::format_seconds_to_minutes;
 
 fn main() {
-    let gtfs = Gtfs::from_url("https://www.arcgis.com/sharing/rest/content/items/aba12fd2cbac4843bc7406151bc66106/data").expect("Could not download gtfs file");
-    //let gtfs = Gtfs::from_path("./gtfs-vienna.zip").expect("Could not open gtfs.zip file");
-    let transit_index = TransitIndex::new(&gtfs);
-    let server = Server::http("0.0.0.0:8000").expect("Failed to start the server");
-
-    for request in server.incoming_requests() {
-        let full_url = format!("http://localhost:8000{}", request.url());
-        let parsed_url = url::Url::parse(&full_url).unwrap();
-        let query_params: HashMap<String, String> = parsed_url
-            .query_pairs()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect();
-
-        let response = match parsed_url.path() {
-            "/api/v1/stops" => {
-                let (stops_data, time_taken) = util::measure(|| {
-                    if let Some(stop_name) = query_params.get("stop_name") {
-                        let results = transit_index.search_by_name(stop_name);
-                        serde_json::json!(vec![if let Some(first_stop_platforms) = results.first() {
-                            serde_json::json!({
-                                "stop_name": first_stop_platforms.stop_name,
-                                "platforms": first_stop_platforms.platforms.iter().map(|platform| {
-                                    serde_json::json!({
-                                        "id": platform.id,
-                                        "longitude": platform.longitude.unwrap_or(0.0),
-                                        "latitude": platform.latitude.unwrap_or(0.0),
-                                        "platform_code": platform.platform_code.as_ref().unwrap_or(&"".to_string()),
-                                        "zone": platform.zone_id.as_ref().unwrap_or(&"".to_string())
-                                    })
-                                }).collect::<Vec<_>>()
-                            })
-                        } else {
-                            serde_json::json!({
-                                "stop_name": "",
-                                "platforms": []
-                            })
-                        }])
-                    } else {
-                        serde_json::json!(transit_index.platforms.iter().map(|(stop_name, stop_platforms)| {
-                            serde_json::json!({
-                                "stop_name": transit_index.get_stop_name_from_id(stop_name),
-                                "platforms": stop_platforms.platforms.iter().map(|platform| {
-                                    serde_json::json!({
-                                        "id": platform.id,
-                                        "longitude": platform.longitude.unwrap_or(0.0),
-                                        "latitude": platform.latitude.unwrap_or(0.0),
-                                        "platform_code": platform.platform_code.as_ref().unwrap_or(&"".to_string()),
-                                        "zone": platform.zone_id.as_ref().unwrap_or(&"".to_string())
-                                    })
-                                }).collect::<Vec<_>>()
-                            })
-                        }).collect::<Vec<_>>())
-                    }
-                });
-            
-                let response = serde_json::json!({
-                    "time_taken": time_taken,
-                    "stops": stops_data
-                });
-            
-                Response::from_string(to_string(&response).unwrap())
-                    .with_status_code(200)
-                    .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
-                    .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
-                    .with_header(Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, POST, PUT, DELETE, OPTIONS"[..]).unwrap())
-            },
-            "/api/v1/stops/routes/departures" => {
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("[!] {}", e);
+        std::process::exit(1);
+    });
+
+    if let Some(Command::BuildIndex { out, .. }) = &config.command {
+        if let Err(e) = build_snapshot(&config, out) {
+            eprintln!("[!] {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let transit_index = reload::load_index(&config).unwrap_or_else(|e| {
+        eprintln!("[!] {}", e);
+        std::process::exit(1);
+    });
+    let config = Arc::new(config);
+    let shared_index = Arc::new(SharedIndex::new(transit_index, config.clone()));
+    let realtime = Arc::new(Realtime::new(&config));
+    realtime.start();
+
+    // Kept alive until the server stops
+    let _watcher = match &config.feed {
+        FeedSource::Path(path) => shared_index
+            .watch(path)
+            .inspect(|_| println!("[i] Watching {} for changes", path.display()))
+            .inspect_err(|e| eprintln!("[!] Could not watch {} for changes: {}", path.display(), e))
+            .ok(),
+        FeedSource::Url(_) => None,
+    };
+
+    let server = Arc::new(Server::http(config.bind_address()).expect("Failed to start the server"));
+    println!("[i] Listening on {} with {} workers", config.bind_address(), config.workers);
+
+    let workers: Vec<_> = (0..config.workers)
+        .map(|_| {
+            let server = server.clone();
+            let shared_index = shared_index.clone();
+            let realtime = realtime.clone();
+            let config = config.clone();
+
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle_request(request, &shared_index, &realtime, &config);
+                }
+            })
+        })
+        .collect();
+
+    workers.into_iter().for_each(|worker| {
+        if worker.join().is_err() {
+            eprintln!("[!] Worker thread panicked");
+        }
+    });
+}
+
+fn build_snapshot(config: &Config, out: &Path) -> Result<(), String> {
+    println!("[i] Loading GTFS feed from {}", config.feed);
+    let feed = config.feed.fetch()?;
+    let feed_checksum = feed.checksum()?;
+    let transit_index = reload::build_index(&feed, config)?;
+
+    snapshot::write(out, &transit_index, feed_checksum)
+        .map_err(|e| format!("Could not write index snapshot to {}: {}", out.display(), e))?;
+    println!("[i] Index snapshot written to {}", out.display());
+    Ok(())
+}
+
+fn handle_request(request: Request, shared_index: &Arc<SharedIndex>, realtime: &Realtime, config: &Config) {
+    // A panic fails only the request it happened in, the worker keeps serving the next ones
+    let response = panic::catch_unwind(AssertUnwindSafe(|| respond_to(&request, shared_index, realtime, config)))
+        .unwrap_or_else(|_| {
+            eprintln!("[!] Request {} panicked", request.url());
+            ApiError::Internal.to_response()
+        });
+
+    if let Err(e) = request.respond(response) {
+        println!("Error sending response: {}", e);
+    }
+}
+
+fn respond_to(request: &Request, shared_index: &Arc<SharedIndex>, realtime: &Realtime, config: &Config) -> Response<Cursor<Vec<u8>>> {
+    // In-flight requests keep the index they started with, even when a reload swaps it meanwhile
+    let transit_index = shared_index.get();
+    // Only the path and the query of the request target matter, the base is never exposed
+    let parsed_url = match url::Url::parse("http://localhost").and_then(|base| base.join(request.url())) {
+        Ok(parsed_url) => parsed_url,
+        Err(_) => return ApiError::InvalidUrl(request.url().to_string()).to_response(),
+    };
+    let query_params: HashMap<String, String> = parsed_url
+        .query_pairs()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+    if parsed_url.path() == "/api/v1/swagger" {
+        return Response::from_string(include_str!("../openapi.yaml"))
+            .with_status_code(200)
+            .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap())
+            .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
+            .with_header(Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, POST, PUT, DELETE, OPTIONS"[..]).unwrap());
+    }
+
+    let result: Result<Value, ApiError> = match parsed_url.path() {
+        "/api/v1/stops" => {
+            let service_alerts = realtime.service_alerts();
+            let now = transit_index.calendar.now().timestamp();
+            let stop_alerts = |stop_platforms: &StopPlatforms| {
+                service_alerts.matching(&stop_entity(&transit_index, stop_platforms), now).into_iter().map(alert_json).collect::<Vec<_>>()
+            };
+            let platform_json = |stop: &StopIdx| {
+                let platform = &transit_index.platforms[*stop as usize];
+                serde_json::json!({
+                    "id": platform.id,
+                    "longitude": platform.longitude.unwrap_or(0.0),
+                    "latitude": platform.latitude.unwrap_or(0.0),
+                    "platform_code": platform.platform_code.as_ref().unwrap_or(&"".to_string()),
+                    "zone": platform.zone_id.as_ref().unwrap_or(&"".to_string())
+                })
+            };
+
+            let (stops_data, time_taken) = util::measure(|| {
                 if let Some(stop_name) = query_params.get("stop_name") {
-                    
-                    let (possibilities, time_taken) = util::measure(|| {
-                        let stop_platforms = transit_index.search_by_name(stop_name)[0].clone();
-                        let mut possibilities: HashMap<&str, BTreeSet<&str>> = HashMap::new();
-
-                        stop_platforms.platforms.iter().for_each(|p| {
-                            if let Some(from) = transit_index.stops_graph.get(p.id()) {
-                                from.values().for_each(|trips| {
-                                    trips.iter().for_each(|dt| {
-                                        if let Ok(route) = gtfs.get_route(&dt.trip.route_id) {
-                                            if let Some(route_name) = &route.short_name {
-                                                let entry = possibilities.entry(route_name.as_str()).or_insert(BTreeSet::new());
-                                                entry.insert(dt.stop_times.last().unwrap().stop.name.as_ref().unwrap().as_str());
-                                            }
-                                        }
-                                    })
-                                })
-                            }
-                        });
-
-                        possibilities
-                    });
-
-                    let response = serde_json::json!({
-                        "time_taken": time_taken,
-                        "possibilities": possibilities,
-                    });
-
-                    Response::from_string(to_string(&response).unwrap()).with_status_code(400)
-                        .with_status_code(200)
-                        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
-                        .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
-                        .with_header(Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, POST, PUT, DELETE, OPTIONS"[..]).unwrap())
+                    let (stop_platforms, search_match) = transit_index.search_stops(stop_name)
+                        .into_iter()
+                        .next()
+                        .ok_or_else(|| ApiError::StopNotFound(stop_name.clone()))?;
+                    Ok(serde_json::json!(vec![serde_json::json!({
+                        "stop_name": stop_platforms.stop_name,
+                        "score": search_match.score,
+                        "platforms": stop_platforms.platforms.iter().map(platform_json).collect::<Vec<_>>(),
+                        "alerts": stop_alerts(stop_platforms),
+                    })]))
                 } else {
-                    let response = serde_json::json!({
-                        "error": "Invalid stop_name query parameter",
-                    });
-
-                    Response::from_string(to_string(&response).unwrap()).with_status_code(400)
-                        .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
-                        .with_header(Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, POST, PUT, DELETE, OPTIONS"[..]).unwrap())
+                    Ok(serde_json::json!(transit_index.platforms.iter().map(|platform| {
+                        let station = &transit_index.stations[platform.station as usize];
+                        serde_json::json!({
+                            "stop_name": platform.name,
+                            "platforms": station.platforms.iter().map(platform_json).collect::<Vec<_>>(),
+                            "alerts": stop_alerts(station),
+                        })
+                    }).collect::<Vec<_>>()))
                 }
-            },
-            "/api/v1/swagger" => {
-                Response::from_string(include_str!("../openapi.yaml"))
-                    .with_status_code(200)
-                    .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap())
-                    .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
-                    .with_header(Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, POST, PUT, DELETE, OPTIONS"[..]).unwrap())
-            },
-            "/api/v1/stops/nearest" => {
-                let parsed_lon = query_params.get("lon").and_then(|s| s.parse::<f64>().ok());
-                let parsed_lat = query_params.get("lat").and_then(|s| s.parse::<f64>().ok());
-                
-                if let (Some(lon), Some(lat)) = (parsed_lon, parsed_lat) {
-                    let max_count = query_params.get("max").and_then(|s| s.parse::<usize>().ok()).unwrap_or(5);
-                    let (nearest_stops, time_taken) = util::measure(|| {
-                        transit_index.find_nearest_stops(lon, lat, max_count)
-                    });
-
-                    let response = serde_json::json!({
-                        "time_taken": time_taken,
-                        "nearest_stops": nearest_stops.iter().map(|stop_platform| {
+            });
+
+            stops_data.map(|stops_data| serde_json::json!({
+                "time_taken": time_taken,
+                "stops": stops_data
+            }))
+        },
+        "/api/v1/stops/autocomplete" => {
+            let (result, time_taken) = util::measure(|| {
+                let query = query_params.get("q").ok_or(ApiError::MissingParameter("q"))?;
+                let limit = parse_param(&query_params, "limit", |s| s.parse::<usize>().ok())?.unwrap_or(10);
+
+                let mut matches = transit_index.search_stops(query);
+                matches.truncate(limit);
+                Ok(matches)
+            });
+
+            result.map(|matches| serde_json::json!({
+                "time_taken": time_taken,
+                "stops": matches.iter().map(|(stop_platforms, search_match)| {
+                    serde_json::json!({
+                        "stop_name": stop_platforms.stop_name,
+                        "ids": stop_platforms.platforms.iter().map(|&stop| &transit_index.platforms[stop as usize].id).collect::<Vec<_>>(),
+                        "score": search_match.score,
+                        "highlight": search_match.highlight.map(|(start, end)| serde_json::json!({
+                            "start": start,
+                            "end": end,
+                        })),
+                    })
+                }).collect::<Vec<_>>(),
+            }))
+        },
+        "/api/v1/stops/routes/departures" => {
+            let (possibilities, time_taken) = util::measure(|| {
+                let stop_name = query_params.get("stop_name").ok_or(ApiError::MissingParameter("stop_name"))?;
+                let stop_platforms = transit_index.find_stop(stop_name).ok_or_else(|| ApiError::StopNotFound(stop_name.clone()))?;
+                let date = parse_param(&query_params, "date", util::parse_date)?
+                    .unwrap_or_else(|| transit_index.calendar.today());
+                let modes = parse_mode_filter(&query_params)?;
+                let active_services = transit_index.calendar.active_services(date);
+                let mut possibilities: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+
+                stop_platforms.platforms.iter().for_each(|&stop| {
+                    transit_index.get_patterns_through(stop).for_each(|(pattern, position)| {
+                        let route = &transit_index.routes[pattern.route as usize];
+                        if !modes.allows(route.mode) || !pattern.services.iter().any(|&service| active_services[service as usize]) {
+                            return;
+                        }
+
+                        if let Some(route_name) = &route.short_name {
+                            let entry = possibilities.entry(route_name.as_str()).or_default();
+                            pattern.stops[position + 1..].iter().for_each(|&stop| {
+                                entry.insert(transit_index.get_stop_name(stop));
+                            });
+                        }
+                    })
+                });
+
+                Ok(possibilities)
+            });
+
+            possibilities.map(|possibilities| serde_json::json!({
+                "time_taken": time_taken,
+                "possibilities": possibilities,
+            }))
+        },
+        "/api/v1/stops/departures" => {
+            let delays = realtime.delays(&transit_index);
+            let service_alerts = realtime.service_alerts();
+            let (result, time_taken) = util::measure(|| {
+                let stop_name = query_params.get("stop_name").ok_or(ApiError::MissingParameter("stop_name"))?;
+                let stop_platforms = transit_index.find_stop(stop_name).ok_or_else(|| ApiError::StopNotFound(stop_name.clone()))?;
+                let date = parse_param(&query_params, "date", util::parse_date)?
+                    .unwrap_or_else(|| transit_index.calendar.today());
+                let from_time = parse_param(&query_params, "from_time", util::parse_time)?
+                    .unwrap_or_else(|| transit_index.calendar.seconds_since_midnight());
+                let limit = parse_param(&query_params, "limit", |s| s.parse::<usize>().ok())?.unwrap_or(10);
+                let modes = parse_mode_filter(&query_params)?;
+
+                let departures = transit_index.get_departures(stop_platforms, date, from_time, limit, modes, &delays);
+                Ok((stop_platforms, date, from_time, departures))
+            });
+
+            result.map(|(stop_platforms, date, from_time, departures)| {
+                let calendar = &transit_index.calendar;
+                let from_time = ServiceTime { date: date.pred_opt().unwrap(), seconds: calendar.search_seconds(date, from_time) };
+                let stop_alerts = service_alerts.matching(&stop_entity(&transit_index, stop_platforms), calendar.timestamp(from_time));
+
+                serde_json::json!({
+                    "time_taken": time_taken,
+                    "stop_name": stop_platforms.stop_name,
+                    "alerts": stop_alerts.iter().map(|alert| alert_json(alert)).collect::<Vec<_>>(),
+                    "departures": departures.iter().map(|departure| {
+                        let entity = InformedEntity {
+                            stop_ids: vec![departure.platform.id.as_str()],
+                            route_id: Some(departure.route.id.as_str()),
+                            trip_id: Some(departure.trip.id.as_str()),
+                        };
+                        let departure_time = departure.get_predicted_departure_time().unwrap_or(departure.get_departure_time());
+
+                        // Alerts of the whole stop are listed once, next to the departures
+                        let departure_alerts = service_alerts
+                            .matching(&entity, calendar.timestamp(departure_time))
+                            .into_iter()
+                            .filter(|alert| !stop_alerts.iter().any(|stop_alert| std::ptr::eq(*stop_alert, *alert)))
+                            .map(alert_json)
+                            .collect::<Vec<_>>();
+
+                        serde_json::json!({
+                            "departure_at": calendar.format_time(departure.get_departure_time()),
+                            "predicted_departure_at": departure.get_predicted_departure_time().map(|time| calendar.format_time(time)),
+                            "route": departure.route.get_name(),
+                            "vehicle_type": departure.route.mode.name(),
+                            "headsign": departure.headsign,
+                            "platform_code": departure.platform.platform_code.as_ref().unwrap_or(&"".to_string()),
+                            "trip_id": departure.trip.id,
+                            "alerts": departure_alerts,
+                        })
+                    }).collect::<Vec<_>>(),
+                })
+            })
+        },
+        "/api/v1/stops/nearest" => {
+            let (result, time_taken) = util::measure(|| {
+                let lon = required_param(&query_params, "lon", |s| s.parse::<f64>().ok())?;
+                let lat = required_param(&query_params, "lat", |s| s.parse::<f64>().ok())?;
+                let max_count = parse_param(&query_params, "max", |s| s.parse::<usize>().ok())?.unwrap_or(5);
+
+                Ok((lon, lat, transit_index.find_nearest_stops(lon, lat, max_count)))
+            });
+
+            result.map(|(lon, lat, nearest_stops)| serde_json::json!({
+                "time_taken": time_taken,
+                "nearest_stops": nearest_stops.iter().map(|stop_platform| {
+                    serde_json::json!({
+                        "stop_name": stop_platform.stop_name,
+                        "distance": format!("{:.2} m", transit_index.distance_to_location(stop_platform, geo::Point::new(lon, lat))),
+                        "platforms": stop_platform.platforms.iter().map(|&stop| {
+                            let stop = &transit_index.platforms[stop as usize];
                             serde_json::json!({
-                                "stop_name": stop_platform.stop_name,
-                                "distance": format!("{:.2} m", stop_platform.distance_to_location(geo::Point::new(lon, lat))),
-                                "platforms": stop_platform.platforms.iter().map(|stop| {
-                                    serde_json::json!({
-                                        "id": stop.id,
-                                        "platform_code": stop.platform_code,
-                                        "zone": stop.zone_id,
-                                        "latitude": stop.latitude,
-                                        "longitude": stop.longitude,
-                                    })
-                                }).collect::<Vec<_>>()
+                                "id": stop.id,
+                                "platform_code": stop.platform_code,
+                                "zone": stop.zone_id,
+                                "latitude": stop.latitude,
+                                "longitude": stop.longitude,
                             })
-                        }).collect::<Vec<_>>(),
-                    });
+                        }).collect::<Vec<_>>()
+                    })
+                }).collect::<Vec<_>>(),
+            }))
+        },
+        "/api/v1/trip" => {
+            let delays = realtime.delays(&transit_index);
+            let service_alerts = realtime.service_alerts();
+            let (route, time_taken) = util::measure(|| {
+                let time_at = parse_param(&query_params, "time_at", util::parse_time)?;
+                let arrive_by = parse_param(&query_params, "arrive_by", util::parse_time)?;
+                let window_start = parse_param(&query_params, "window_start", util::parse_time)?;
+                let window_end = parse_param(&query_params, "window_end", util::parse_time)?;
+                let window = match (window_start, window_end) {
+                    (Some(start), Some(end)) if start <= end => Some(TimeConstraint::DepartBetween(start, end)),
+                    (Some(_), Some(_)) => return Err(ApiError::InvalidParameter("window_end")),
+                    (Some(_), None) => return Err(ApiError::MissingParameter("window_end")),
+                    (None, Some(_)) => return Err(ApiError::MissingParameter("window_start")),
+                    (None, None) => None,
+                };
+                // None of the time constraints takes precedence, they can not be combined
+                let mut constraints = [
+                    ("time_at", time_at.map(TimeConstraint::DepartAt)),
+                    ("arrive_by", arrive_by.map(TimeConstraint::ArriveBy)),
+                    ("window_start", window),
+                ].into_iter().filter(|(_, constraint)| constraint.is_some());
+                let time = match (constraints.next(), constraints.next()) {
+                    (Some((first, _)), Some((second, _))) => return Err(ApiError::ConflictingParameters(first, second)),
+                    (constraint, _) => constraint.and_then(|(_, time)| time),
+                };
             
+                let date = parse_param(&query_params, "date", util::parse_date)?
+                    .unwrap_or_else(|| transit_index.calendar.today());
 
-                    Response::from_string(to_string(&response).unwrap())
-                        .with_status_code(200)
-                        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
-                        .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
-                        .with_header(Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, POST, PUT, DELETE, OPTIONS"[..]).unwrap())
-                } else {
-                    let response = serde_json::json!({
-                        "error": "Invalid lon and lat query parameters",
-                    });
+                let max_transfers = parse_param(&query_params, "max_transfers", |s| s.parse::<usize>().ok())?;
+                let sort = parse_param(&query_params, "sort", SortBy::from_name)?;
+                // A range query returns the journeys in the order of their departures
+                if window.is_some() && sort.is_some() {
+                    return Err(ApiError::ConflictingParameters("window_start", "sort"));
+                }
+                let options = JourneyOptions {
+                    max_transfers: max_transfers.unwrap_or(config.max_transfers),
+                    modes: parse_mode_filter(&query_params)?,
+                    // Range queries return every journey of the window unless limited
+                    num_itineraries: parse_param(&query_params, "num_itineraries", |s| s.parse::<usize>().ok().filter(|&n| n > 0))?
+                        .unwrap_or(if window.is_some() { usize::MAX } else { 1 }),
+                    sort: sort.unwrap_or(SortBy::Time),
+                    algorithm: parse_param(&query_params, "algorithm", Algorithm::from_name)?.unwrap_or(Algorithm::Raptor),
+                };
 
-                    Response::from_string(to_string(&response).unwrap()).with_status_code(400)
-                        .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
-                        .with_header(Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, POST, PUT, DELETE, OPTIONS"[..]).unwrap())
+                // The connection scan answers only the earliest arrival for a departure time
+                let is_single_journey = options.num_itineraries == 1 && options.sort == SortBy::Time;
+                if options.algorithm == Algorithm::Csa && !(is_single_journey && matches!(time, None | Some(TimeConstraint::DepartAt(_)))) {
+                    return Err(ApiError::InvalidParameter("algorithm"));
                 }
-            },
-            "/api/v1/trip" => {
-                let (route, time_taken) = util::measure(|| {
-                    let from_param = query_params.get("from").map_or("Cintorin Slavicie", |v| v.as_str());
-                    let to_param = query_params.get("to").map_or("Hlavna stanica", |v| v.as_str());
-                
-                    let time_at = query_params.get("time_at").and_then(|time_str| {
-                        time_str.split(':').map(|s| s.parse::<u32>().ok()).collect::<Option<Vec<u32>>>().and_then(|parts| {
-                            if parts.len() == 2 {
-                                Some(parts[0] * 3600 + parts[1] * 60)
-                            } else {
-                                None
-                            }
-                        })
-                    });
-                
-                    let from_stop = transit_index.search_by_name(from_param).get(0).cloned().expect("Stop not found");
-                    let to_stop = transit_index.search_by_name(to_param).get(0).cloned().expect("Stop not found");
-                
-                    let route = transit_index.find_route(from_stop, to_stop, time_at);
-                    route
-                });
-                
-                let response = route.map(|trips| {
-                    let first_trip_departure = trips.first().map(|t| format_u32_time(t.get_departure_time()));
-                    let last_trip_arrival = trips.last().map(|t| format_u32_time(t.get_arrival_time()));
+                // Connections are scanned once without counting the trips, so the transfers can not be limited
+                if options.algorithm == Algorithm::Csa && max_transfers.is_some() {
+                    return Err(ApiError::InvalidParameter("max_transfers"));
+                }
+
+                // Coordinates take precedence over the stop name
+                let parse_place = |(name_param, lon_param, lat_param), default_name: &str| -> Result<Place, ApiError> {
+                    let lon = parse_param(&query_params, lon_param, |s| s.parse::<f64>().ok())?;
+                    let lat = parse_param(&query_params, lat_param, |s| s.parse::<f64>().ok())?;
+
+                    match (lon, lat) {
+                        (Some(longitude), Some(latitude)) => Ok(Place::Coordinates { longitude, latitude }),
+                        _ => {
+                            let name = query_params.get(name_param).map_or(default_name, |v| v.as_str());
+                            let stop_platforms = transit_index.find_stop(name).ok_or_else(|| ApiError::StopNotFound(name.to_string()))?;
+                            Ok(Place::Stop(stop_platforms))
+                        },
+                    }
+                };
+
+                let from = parse_place(("from", "from_lon", "from_lat"), "Cintorin Slavicie")?;
+                let to = parse_place(("to", "to_lon", "to_lat"), "Hlavna stanica")?;
             
+                let itineraries = transit_index.find_route(from, to, date, time, options, &delays);
+                if itineraries.is_empty() {
+                    return Err(ApiError::NoRouteFound);
+                }
+                Ok(itineraries)
+            });
+            
+            route.map(|itineraries| {
+                let calendar = &transit_index.calendar;
+                let format_time = |time: ServiceTime| calendar.format_time(time);
+                // Alerts of the trip and its route, or of the stops the trip is boarded and left at
+                let trip_alerts = |trip: &transit_index::DirectTrip| {
+                    let entity = InformedEntity {
+                        stop_ids: vec![trip.stops.first().unwrap().id.as_str(), trip.stops.last().unwrap().id.as_str()],
+                        route_id: Some(trip.route.id.as_str()),
+                        trip_id: Some(trip.trip.id.as_str()),
+                    };
+                    let departure_time = trip.get_predicted_departure_time().unwrap_or(trip.get_departure_time());
+                    service_alerts.matching(&entity, calendar.timestamp(departure_time)).into_iter().map(alert_json).collect::<Vec<_>>()
+                };
+                let itinerary_json = |legs: &Vec<Leg>| {
+                    let first_leg_departure = legs.first().map(|l| format_time(l.get_departure_time()));
+                    let last_leg_arrival = legs.last().map(|l| format_time(l.get_arrival_time()));
+                    let walking_distance: f64 = legs.iter().map(|leg| match leg {
+                        Leg::Walk(walk) => walk.distance,
+                        Leg::Transit(_) => 0.0,
+                    }).sum();
+
                     serde_json::json!({
-                        "time_taken": time_taken,
-                        "departure_at": first_trip_departure,
-                        "arrival_at": last_trip_arrival,
-                        "trips": trips.iter().map(|trip| {
-                            serde_json::json!({
-                                "departure_at": format_u32_time(trip.get_departure_time()),
-                                "arrival_at": format_u32_time(trip.get_arrival_time()),
-                                "trip_id": trip.trip.id(),
+                        "departure_at": first_leg_departure,
+                        "arrival_at": last_leg_arrival,
+                        "transfers": transit_index.count_transfers(legs),
+                        "walking_distance": format!("{:.0} m", walking_distance),
+                        "trips": legs.iter().map(|leg| match leg {
+                            Leg::Transit(trip) => serde_json::json!({
+                                "type": "transit",
+                                "departure_at": format_time(trip.get_departure_time()),
+                                "arrival_at": format_time(trip.get_arrival_time()),
+                                "predicted_departure_at": trip.get_predicted_departure_time().map(format_time),
+                                "predicted_arrival_at": trip.get_predicted_arrival_time().map(format_time),
+                                "trip_id": trip.trip.id,
                                 "duration": format_seconds_to_minutes(trip.get_duration()),
-                                "route": gtfs.get_route(&trip.trip.route_id).map_or("-".to_string(), |r| r.short_name.clone().unwrap_or("-".to_string())),
+                                "route": trip.route.get_name(),
+                                "vehicle_type": trip.route.mode.name(),
                                 "stop_names": trip.get_stop_names(),
-                            })
+                                "alerts": trip_alerts(trip),
+                            }),
+                            Leg::Walk(walk) => serde_json::json!({
+                                "type": "walk",
+                                "departure_at": format_time(leg.get_departure_time()),
+                                "arrival_at": format_time(leg.get_arrival_time()),
+                                "duration": format_seconds_to_minutes(walk.get_duration()),
+                                "distance": format!("{:.0} m", walk.distance),
+                                "stop_names": [
+                                    walk.from_stop.map(|stop| transit_index.get_stop_name(stop)),
+                                    walk.to_stop.map(|stop| transit_index.get_stop_name(stop)),
+                                ],
+                            }),
                         }).collect::<Vec<_>>()
                     })
-                }).unwrap_or(serde_json::json!({
-                    "error": "No route found"
-                }));
-
-                Response::from_string(to_string(&response).unwrap())
-                    .with_status_code(200)
-                    .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
-                    .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
-                    .with_header(Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, POST, PUT, DELETE, OPTIONS"[..]).unwrap())
-            },
-            _ => Response::from_string("Not Found")
-        };
-        
-        if let Err(e) = request.respond(response) {
-            println!("Error sending response: {}", e);
-        }
+                };
+
+                let itineraries: Vec<_> = itineraries.iter().map(itinerary_json).collect();
+                // The best itinerary stays at the top level for the clients expecting a single journey
+                let mut response = itineraries[0].clone();
+                response["time_taken"] = serde_json::json!(time_taken);
+                response["itineraries"] = serde_json::json!(itineraries);
+                response
+            })
+        },
+        "/api/v1/vehicles" => {
+            let vehicle_positions = realtime.vehicle_positions();
+            let (result, time_taken) = util::measure(|| {
+                let filter = VehicleFilter {
+                    bbox: parse_param(&query_params, "bbox", util::parse_bbox)?,
+                    route: query_params.get("route").map(|s| s.as_str()),
+                    trip_id: query_params.get("trip_id").map(|s| s.as_str()),
+                };
+
+                Ok(realtime::find_vehicles(&transit_index, &vehicle_positions, &filter))
+            });
+
+            result.map(|vehicles| serde_json::json!({
+                "time_taken": time_taken,
+                "vehicles": vehicles.iter().map(|vehicle| {
+                    serde_json::json!({
+                        "vehicle_id": vehicle.id,
+                        "label": vehicle.label,
+                        "latitude": vehicle.latitude,
+                        "longitude": vehicle.longitude,
+                        "bearing": vehicle.bearing,
+                        "speed": vehicle.speed,
+                        "trip_id": vehicle.trip_id,
+                        "route": vehicle.route.map(|route| route.get_name()),
+                        "headsign": vehicle.trip.and_then(|trip| trip.headsign.as_ref()),
+                        "current_stop": vehicle.current_stop.map(|stop| serde_json::json!({
+                            "id": stop.id,
+                            "stop_name": stop.name,
+                            "platform_code": stop.platform_code,
+                        })),
+                        "current_status": vehicle.current_status,
+                        "timestamp": vehicle.timestamp,
+                    })
+                }).collect::<Vec<_>>(),
+            }))
+        },
+        "/api/v1/alerts" => {
+            let service_alerts = realtime.service_alerts();
+            let now = transit_index.calendar.now().timestamp();
+
+            let (alerts, time_taken) = util::measure(|| {
+                service_alerts.all().iter().map(|alert| {
+                    let mut alert_json = alert_json(alert);
+                    alert_json["active"] = serde_json::json!(alert.is_active_at(now));
+                    alert_json["informed_entities"] = alert.informed_entities.iter().map(|selector| {
+                        let route_id = selector.route_id.as_ref().or(selector.trip.as_ref().and_then(|trip| trip.route_id.as_ref()));
+                        serde_json::json!({
+                            "agency_id": selector.agency_id,
+                            "route_id": route_id,
+                            "route": route_id.and_then(|route_id| transit_index.get_route_by_id(route_id)).map(|route| route.get_name()),
+                            "trip_id": selector.trip.as_ref().and_then(|trip| trip.trip_id.as_ref()),
+                            "stop_id": selector.stop_id,
+                            "stop_name": selector.stop_id.as_ref().and_then(|stop_id| transit_index.get_platform_by_id(stop_id)).map(|stop| &stop.name),
+                        })
+                    }).collect();
+                    alert_json
+                }).collect::<Vec<_>>()
+            });
+
+            Ok(serde_json::json!({
+                "time_taken": time_taken,
+                "alerts": alerts,
+            }))
+        },
+        "/api/v1/admin/reload" => {
+            let authorization = request.headers().iter()
+                .find(|header| header.field.equiv("Authorization"))
+                .map(|header| header.value.as_str());
+
+            if *request.method() != Method::Post {
+                Err(ApiError::MethodNotAllowed(request.method().to_string()))
+            } else if !reload::is_authorized(config.admin_token.as_deref(), request.remote_addr(), authorization) {
+                Err(ApiError::Forbidden)
+            } else if shared_index.reload() {
+                Ok(serde_json::json!({ "status": "reloading" }))
+            } else {
+                Err(ApiError::ReloadInProgress)
+            }
+        },
+        path => Err(ApiError::NotFound(path.to_string())),
+    };
+
+    match result {
+        Ok(body) => Response::from_string(to_string(&body).unwrap())
+            .with_status_code(200)
+            .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
+            .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
+            .with_header(Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, POST, PUT, DELETE, OPTIONS"[..]).unwrap()),
+        Err(e) => e.to_response(),
     }
-}
\ No newline at end of file
+}
+
+/// Every platform of the stop, for the alerts concerning the stop as a whole
+fn stop_entity<'a>(transit_index: &'a TransitIndex, stop_platforms: &StopPlatforms) -> InformedEntity<'a> {
+    InformedEntity {
+        stop_ids: stop_platforms.platforms.iter().map(|&stop| transit_index.platforms[stop as usize].id.as_str()).collect(),
+        route_id: None,
+        trip_id: None,
+    }
+}
+
+fn alert_json(alert: &ServiceAlert) -> Value {
+    serde_json::json!({
+        "id": alert.id,
+        "cause": alert.cause,
+        "effect": alert.effect,
+        "header": alert.header,
+        "description": alert.description,
+        "url": alert.url,
+        "active_periods": alert.active_periods.iter().map(|&(start, end)| serde_json::json!({
+            "start": start,
+            "end": end,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+/// Parses an optional query parameter, a present but malformed value is an error
+fn parse_param<T>(query_params: &HashMap<String, String>, name: &'static str, parse: impl Fn(&str) -> Option<T>) -> Result<Option<T>, ApiError> {
+    query_params
+        .get(name)
+        .map(|value| parse(value).ok_or(ApiError::InvalidParameter(name)))
+        .transpose()
+}
+
+/// Reads the modes and exclude_modes query parameters
+fn parse_mode_filter(query_params: &HashMap<String, String>) -> Result<ModeFilter, ApiError> {
+    Ok(ModeFilter::new(
+        parse_param(query_params, "modes", util::parse_modes)?,
+        parse_param(query_params, "exclude_modes", util::parse_modes)?,
+    ))
+}
+
+fn required_param<T>(query_params: &HashMap<String, String>, name: &'static str, parse: impl Fn(&str) -> Option<T>) -> Result<T, ApiError> {
+    parse_param(query_params, name, parse)?.ok_or(ApiError::MissingParameter(name))
+}
@@ -0,0 +1,149 @@
+//! Owned representation of the feed the index is built from. Stops, routes, trips
+//! and services refer to each other by dense `u32` indices, the GTFS string ids are
+//! kept only to be looked up and returned in responses
+
+use gtfs_structures::RouteType;
+use serde::{Deserialize, Serialize};
+
+/// Index into `TransitIndex::platforms`
+pub type StopIdx = u32;
+/// Index into `TransitIndex::stations`
+pub type StationIdx = u32;
+/// Index into `TransitIndex::routes`
+pub type RouteIdx = u32;
+/// Index into `TransitIndex::trips`
+pub type TripIdx = u32;
+/// Index into the services of `ServiceCalendar`
+pub type ServiceIdx = u32;
+
+/// A single stop from stops.txt, usually one platform of a station
+#[derive(Serialize, Deserialize)]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    pub station: StationIdx,
+    pub longitude: Option<f64>,
+    pub latitude: Option<f64>,
+    pub platform_code: Option<String>,
+    pub zone_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Route {
+    pub id: String,
+    pub short_name: Option<String>,
+    pub mode: Mode,
+}
+
+impl Route {
+    pub fn get_name(&self) -> &str {
+        self.short_name.as_deref().unwrap_or("-")
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Trip {
+    pub id: String,
+    pub route: RouteIdx,
+    pub service: ServiceIdx,
+    pub headsign: Option<String>,
+}
+
+/// Type of vehicles serving a route, from the route_type of routes.txt
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    Tram,
+    Subway,
+    Rail,
+    Bus,
+    Trolleybus,
+    Ferry,
+    CableTram,
+    AerialLift,
+    Funicular,
+    Monorail,
+    Other,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 11] = [
+        Mode::Tram,
+        Mode::Subway,
+        Mode::Rail,
+        Mode::Bus,
+        Mode::Trolleybus,
+        Mode::Ferry,
+        Mode::CableTram,
+        Mode::AerialLift,
+        Mode::Funicular,
+        Mode::Monorail,
+        Mode::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Tram => "tram",
+            Mode::Subway => "subway",
+            Mode::Rail => "rail",
+            Mode::Bus => "bus",
+            Mode::Trolleybus => "trolleybus",
+            Mode::Ferry => "ferry",
+            Mode::CableTram => "cable_tram",
+            Mode::AerialLift => "aerial_lift",
+            Mode::Funicular => "funicular",
+            Mode::Monorail => "monorail",
+            Mode::Other => "other",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Mode::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+}
+
+/// Extended route types are kept by the feed parser as they are, so they are mapped
+/// by their ranges. The basic trolleybus (11) and monorail (12) are kept as well
+impl From<RouteType> for Mode {
+    fn from(route_type: RouteType) -> Self {
+        match route_type {
+            RouteType::Tramway => Mode::Tram,
+            RouteType::Subway => Mode::Subway,
+            RouteType::Rail => Mode::Rail,
+            RouteType::Bus | RouteType::Coach => Mode::Bus,
+            RouteType::Ferry => Mode::Ferry,
+            RouteType::CableCar => Mode::CableTram,
+            RouteType::Gondola => Mode::AerialLift,
+            RouteType::Funicular => Mode::Funicular,
+            RouteType::Other(11 | 800..=899) => Mode::Trolleybus,
+            RouteType::Other(12 | 405) => Mode::Monorail,
+            RouteType::Other(100..=199 | 300..=399) => Mode::Rail,
+            RouteType::Other(200..=299 | 700..=799) => Mode::Bus,
+            RouteType::Other(400..=699) => Mode::Subway,
+            RouteType::Other(900..=999) => Mode::Tram,
+            RouteType::Other(1000..=1099 | 1200..=1299) => Mode::Ferry,
+            RouteType::Other(1300..=1399) => Mode::AerialLift,
+            RouteType::Other(1400..=1499) => Mode::Funicular,
+            RouteType::Air | RouteType::Taxi | RouteType::Other(_) => Mode::Other,
+        }
+    }
+}
+
+/// Modes a request is limited to, all of them unless restricted
+#[derive(Clone, Copy)]
+pub struct ModeFilter {
+    allowed: [bool; Mode::ALL.len()],
+}
+
+impl ModeFilter {
+    /// Allows only the listed modes, or all of them when there is no list, except for the excluded ones
+    pub fn new(modes: Option<Vec<Mode>>, excluded_modes: Option<Vec<Mode>>) -> Self {
+        let mut allowed = [modes.is_none(); Mode::ALL.len()];
+        modes.unwrap_or_default().into_iter().for_each(|mode| allowed[mode as usize] = true);
+        excluded_modes.unwrap_or_default().into_iter().for_each(|mode| allowed[mode as usize] = false);
+        ModeFilter { allowed }
+    }
+
+    pub fn allows(&self, mode: Mode) -> bool {
+        self.allowed[mode as usize]
+    }
+}
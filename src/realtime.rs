@@ -0,0 +1,165 @@
+//! GTFS-Realtime feeds, polled in the background and applied on top of the static index
+
+use std::{
+    sync::{Arc, RwLock, Weak},
+    thread,
+    time::Duration,
+};
+use prost::Message;
+use crate::{config::{Config, FeedSource}, transit_index::TransitIndex};
+
+mod alerts;
+mod proto;
+mod trip_updates;
+mod vehicles;
+
+#[cfg(test)]
+mod tests;
+
+pub use alerts::{InformedEntity, ServiceAlert, ServiceAlerts};
+pub use proto::VehiclePosition;
+pub use trip_updates::{DelayOverlay, RealtimeTimes};
+pub use vehicles::{find_vehicles, VehicleFilter};
+
+pub struct Realtime {
+    trip_updates_source: Option<FeedSource>,
+    vehicle_positions_source: Option<FeedSource>,
+    service_alerts_source: Option<FeedSource>,
+    interval: Duration,
+    state: RwLock<RealtimeState>,
+}
+
+#[derive(Default)]
+struct RealtimeState {
+    trip_updates: Arc<Vec<proto::TripUpdate>>,
+    /// Delays resolved against the index they were last requested for,
+    /// dropped whenever new trip updates arrive
+    delays: Option<(Weak<TransitIndex>, Arc<DelayOverlay>)>,
+    vehicle_positions: Arc<Vec<VehiclePosition>>,
+    service_alerts: Arc<ServiceAlerts>,
+}
+
+impl Realtime {
+    pub fn new(config: &Config) -> Self {
+        Realtime {
+            trip_updates_source: config.trip_updates.clone(),
+            vehicle_positions_source: config.vehicle_positions.clone(),
+            service_alerts_source: config.service_alerts.clone(),
+            interval: Duration::from_secs(config.realtime_interval),
+            state: RwLock::new(RealtimeState::default()),
+        }
+    }
+
+    /// Polls the configured feeds in background threads
+    pub fn start(self: &Arc<Self>) {
+        if let Some(source) = self.trip_updates_source.clone() {
+            let realtime = self.clone();
+            self.poll(source, "trip updates", move |feed| realtime.set_trip_updates(feed));
+        }
+
+        if let Some(source) = self.vehicle_positions_source.clone() {
+            let realtime = self.clone();
+            self.poll(source, "vehicle positions", move |feed| realtime.set_vehicle_positions(feed));
+        }
+
+        if let Some(source) = self.service_alerts_source.clone() {
+            let realtime = self.clone();
+            self.poll(source, "service alerts", move |feed| realtime.set_service_alerts(feed));
+        }
+    }
+
+    fn poll(&self, source: FeedSource, name: &'static str, handle: impl Fn(proto::FeedMessage) -> usize + Send + 'static) {
+        let interval = self.interval;
+
+        thread::spawn(move || {
+            let mut loaded = false;
+
+            loop {
+                let feed = source.read().and_then(|bytes| {
+                    proto::FeedMessage::decode(bytes.as_slice()).map_err(|e| format!("Could not decode {}: {}", source, e))
+                });
+
+                match feed {
+                    Ok(feed) => {
+                        let count = handle(feed);
+                        if !loaded {
+                            println!("[i] Loaded {} {} from {}", count, name, source);
+                            loaded = true;
+                        }
+                    },
+                    Err(e) => eprintln!("[!] Could not load {}: {}", name, e),
+                }
+
+                thread::sleep(interval);
+            }
+        });
+    }
+
+    fn set_trip_updates(&self, feed: proto::FeedMessage) -> usize {
+        let trip_updates: Vec<proto::TripUpdate> = feed.entity
+            .into_iter()
+            .filter(|entity| !entity.is_deleted())
+            .filter_map(|entity| entity.trip_update)
+            .collect();
+        let count = trip_updates.len();
+
+        let mut state = self.state.write().unwrap();
+        state.trip_updates = Arc::new(trip_updates);
+        state.delays = None;
+        count
+    }
+
+    fn set_vehicle_positions(&self, feed: proto::FeedMessage) -> usize {
+        let vehicle_positions: Vec<VehiclePosition> = feed.entity
+            .into_iter()
+            .filter(|entity| !entity.is_deleted())
+            .filter_map(|entity| entity.vehicle)
+            .collect();
+        let count = vehicle_positions.len();
+
+        self.state.write().unwrap().vehicle_positions = Arc::new(vehicle_positions);
+        count
+    }
+
+    fn set_service_alerts(&self, feed: proto::FeedMessage) -> usize {
+        let alerts: Vec<ServiceAlert> = feed.entity
+            .into_iter()
+            .filter(|entity| !entity.is_deleted())
+            .filter_map(|entity| Some(ServiceAlert::new(entity.id, entity.alert?)))
+            .collect();
+        let count = alerts.len();
+
+        self.state.write().unwrap().service_alerts = Arc::new(ServiceAlerts::new(alerts));
+        count
+    }
+
+    pub fn vehicle_positions(&self) -> Arc<Vec<VehiclePosition>> {
+        self.state.read().unwrap().vehicle_positions.clone()
+    }
+
+    pub fn service_alerts(&self) -> Arc<ServiceAlerts> {
+        self.state.read().unwrap().service_alerts.clone()
+    }
+
+    /// Returns the delays of the latest trip updates, resolved against the index
+    pub fn delays(&self, index: &Arc<TransitIndex>) -> Arc<DelayOverlay> {
+        let trip_updates = {
+            let state = self.state.read().unwrap();
+            if let Some((resolved_for, delays)) = &state.delays {
+                if resolved_for.as_ptr() == Arc::as_ptr(index) {
+                    return delays.clone();
+                }
+            }
+            state.trip_updates.clone()
+        };
+
+        let delays = Arc::new(DelayOverlay::new(index, &trip_updates, index.calendar.now()));
+
+        // Unless newer updates arrived meanwhile, keep the delays for the following requests
+        let mut state = self.state.write().unwrap();
+        if Arc::ptr_eq(&state.trip_updates, &trip_updates) {
+            state.delays = Some((Arc::downgrade(index), delays.clone()));
+        }
+        delays
+    }
+}
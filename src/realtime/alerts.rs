@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use super::proto::{Alert, Cause, Effect, EntitySelector, TranslatedString};
+
+/// Alert of the ServiceAlerts feed, with a single translation of its texts
+pub struct ServiceAlert {
+    pub id: String,
+    /// (start, end) in POSIX time, open ended when a bound is missing. Always active when empty
+    pub active_periods: Vec<(Option<u64>, Option<u64>)>,
+    pub informed_entities: Vec<EntitySelector>,
+    pub cause: &'static str,
+    pub effect: &'static str,
+    pub header: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+}
+
+impl ServiceAlert {
+    pub fn new(id: String, alert: Alert) -> Self {
+        ServiceAlert {
+            id,
+            active_periods: alert.active_period.iter().map(|period| (period.start, period.end)).collect(),
+            cause: match alert.cause() {
+                Cause::UnknownCause => "unknown_cause",
+                Cause::OtherCause => "other_cause",
+                Cause::TechnicalProblem => "technical_problem",
+                Cause::Strike => "strike",
+                Cause::Demonstration => "demonstration",
+                Cause::Accident => "accident",
+                Cause::Holiday => "holiday",
+                Cause::Weather => "weather",
+                Cause::Maintenance => "maintenance",
+                Cause::Construction => "construction",
+                Cause::PoliceActivity => "police_activity",
+                Cause::MedicalEmergency => "medical_emergency",
+            },
+            effect: match alert.effect() {
+                Effect::NoService => "no_service",
+                Effect::ReducedService => "reduced_service",
+                Effect::SignificantDelays => "significant_delays",
+                Effect::Detour => "detour",
+                Effect::AdditionalService => "additional_service",
+                Effect::ModifiedService => "modified_service",
+                Effect::OtherEffect => "other_effect",
+                Effect::UnknownEffect => "unknown_effect",
+                Effect::StopMoved => "stop_moved",
+                Effect::NoEffect => "no_effect",
+                Effect::AccessibilityIssue => "accessibility_issue",
+            },
+            header: alert.header_text.and_then(translate),
+            description: alert.description_text.and_then(translate),
+            url: alert.url.and_then(translate),
+            informed_entities: alert.informed_entity,
+        }
+    }
+
+    pub fn is_active_at(&self, time: i64) -> bool {
+        self.active_periods.is_empty() || self.active_periods.iter().any(|&(start, end)| {
+            start.is_none_or(|start| start as i64 <= time) && end.is_none_or(|end| time < end as i64)
+        })
+    }
+}
+
+/// Prefers the translation without a language, which is the text of a single language feed
+fn translate(text: TranslatedString) -> Option<String> {
+    let position = text.translation.iter().position(|t| t.language.is_none()).unwrap_or(0);
+    text.translation.into_iter().nth(position).map(|t| t.text)
+}
+
+/// Stop, route or trip of a response the matching alerts are attached to.
+/// Fields left empty do not restrict the alerts
+pub struct InformedEntity<'a> {
+    /// stop_id of every platform of the stop
+    pub stop_ids: Vec<&'a str>,
+    pub route_id: Option<&'a str>,
+    pub trip_id: Option<&'a str>,
+}
+
+/// Alerts indexed by the ids of the stops, routes and trips they inform about
+#[derive(Default)]
+pub struct ServiceAlerts {
+    alerts: Vec<ServiceAlert>,
+    by_stop: HashMap<String, Vec<usize>>,
+    by_route: HashMap<String, Vec<usize>>,
+    by_trip: HashMap<String, Vec<usize>>,
+    /// Alerts informing about a whole agency, which concern every entity
+    network: Vec<usize>,
+}
+
+impl ServiceAlerts {
+    pub fn new(alerts: Vec<ServiceAlert>) -> Self {
+        let mut service_alerts = ServiceAlerts::default();
+
+        alerts.iter().enumerate().for_each(|(alert_idx, alert)| {
+            alert.informed_entities.iter().for_each(|selector| {
+                let (stop_id, route_id, trip_id) = selected_ids(selector);
+
+                if stop_id.is_none() && route_id.is_none() && trip_id.is_none() {
+                    // Selectors of a route type only are not supported
+                    if selector.agency_id.is_some() && selector.route_type.is_none() {
+                        service_alerts.network.push(alert_idx);
+                    }
+                    return;
+                }
+
+                let entries = [
+                    (stop_id, &mut service_alerts.by_stop),
+                    (route_id, &mut service_alerts.by_route),
+                    (trip_id, &mut service_alerts.by_trip),
+                ];
+                entries.into_iter().for_each(|(id, alerts_by_id)| {
+                    if let Some(id) = id {
+                        alerts_by_id.entry(id.to_string()).or_default().push(alert_idx);
+                    }
+                });
+            });
+        });
+
+        service_alerts.alerts = alerts;
+        service_alerts
+    }
+
+    pub fn all(&self) -> &[ServiceAlert] {
+        &self.alerts
+    }
+
+    /// Returns the alerts active at the POSIX time with an informed entity matching the entity
+    pub fn matching(&self, entity: &InformedEntity, time: i64) -> Vec<&ServiceAlert> {
+        let mut candidates: Vec<usize> = entity.stop_ids
+            .iter()
+            .filter_map(|&stop_id| self.by_stop.get(stop_id))
+            .chain(entity.route_id.and_then(|route_id| self.by_route.get(route_id)))
+            .chain(entity.trip_id.and_then(|trip_id| self.by_trip.get(trip_id)))
+            .flatten()
+            .chain(self.network.iter())
+            .copied()
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        candidates
+            .into_iter()
+            .map(|alert_idx| &self.alerts[alert_idx])
+            .filter(|alert| alert.is_active_at(time))
+            .filter(|alert| alert.informed_entities.iter().any(|selector| selector_matches(selector, entity)))
+            .collect()
+    }
+}
+
+/// (stop_id, route_id, trip_id) of the selector, the route can also come from its trip
+fn selected_ids(selector: &EntitySelector) -> (Option<&str>, Option<&str>, Option<&str>) {
+    let trip = selector.trip.as_ref();
+    (
+        selector.stop_id.as_deref(),
+        selector.route_id.as_deref().or(trip.and_then(|trip| trip.route_id.as_deref())),
+        trip.and_then(|trip| trip.trip_id.as_deref()),
+    )
+}
+
+/// Every id present in both the selector and the entity has to be equal, and at least one
+/// has to be present in both, unless the selector informs about the whole agency
+fn selector_matches(selector: &EntitySelector, entity: &InformedEntity) -> bool {
+    let (stop_id, route_id, trip_id) = selected_ids(selector);
+
+    if stop_id.is_none() && route_id.is_none() && trip_id.is_none() {
+        return selector.agency_id.is_some() && selector.route_type.is_none();
+    }
+
+    let stop = stop_id.filter(|_| !entity.stop_ids.is_empty()).map(|stop_id| entity.stop_ids.contains(&stop_id));
+    let route = route_id.zip(entity.route_id).map(|(selected, route_id)| selected == route_id);
+    let trip = trip_id.zip(entity.trip_id).map(|(selected, trip_id)| selected == trip_id);
+    let compared = [stop, route, trip];
+
+    compared.iter().any(Option::is_some) && compared.iter().all(|&equal| equal != Some(false))
+}
@@ -0,0 +1,209 @@
+//! Feeds encoded with the protobuf messages and decoded back, as if read from a file,
+//! applied to a single line of three stops with two trips
+
+use chrono::{NaiveDate, TimeZone};
+use prost::Message;
+use crate::{test_feed, transit_index::TransitIndex};
+use super::{
+    find_vehicles,
+    proto::{
+        Alert, EntitySelector, FeedEntity, FeedHeader, FeedMessage, Position, StopTimeEvent, StopTimeScheduleRelationship,
+        StopTimeUpdate, TimeRange, TranslatedString, Translation, TripDescriptor, TripScheduleRelationship, TripUpdate,
+        VehicleDescriptor, VehiclePosition, VehicleStopStatus,
+    },
+    DelayOverlay, InformedEntity, ServiceAlert, ServiceAlerts, VehicleFilter,
+};
+
+const DEPARTURE: u32 = 8 * 3600;
+
+fn date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()
+}
+
+fn index() -> TransitIndex {
+    let mut stop_times = String::from("trip_id,arrival_time,departure_time,stop_id,stop_sequence\n");
+    for (trip_id, start) in [("T1", DEPARTURE), ("T2", DEPARTURE + 1200)] {
+        for (position, stop_id) in ["P1", "P2", "P3"].iter().enumerate() {
+            let time = start + position as u32 * 300;
+            let time = format!("{:02}:{:02}:00", time / 3600, time / 60 % 60);
+            stop_times.push_str(&format!("{},{},{},{},{}\n", trip_id, time, time, stop_id, position + 1));
+        }
+    }
+
+    test_feed::index(&[
+        ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\nP1,First,48.10,17.10\nP2,Second,48.11,17.11\nP3,Third,48.12,17.12\n"),
+        ("routes.txt", "route_id,route_short_name,route_type\nR,1,3\n"),
+        ("trips.txt", "route_id,service_id,trip_id\nR,WD,T1\nR,WD,T2\n"),
+        ("stop_times.txt", &stop_times),
+    ])
+}
+
+/// Encodes the entities into a feed and decodes it again
+fn round_trip(entities: Vec<FeedEntity>) -> Vec<FeedEntity> {
+    let feed = FeedMessage {
+        header: FeedHeader { gtfs_realtime_version: "2.0".to_string(), timestamp: Some(1_792_396_800) },
+        entity: entities,
+    };
+    FeedMessage::decode(feed.encode_to_vec().as_slice()).unwrap().entity
+}
+
+fn trip_update(trip_id: &str, schedule_relationship: TripScheduleRelationship, stop_time_update: Vec<StopTimeUpdate>) -> FeedEntity {
+    let trip = TripDescriptor {
+        trip_id: Some(trip_id.to_string()),
+        start_date: Some(date().format("%Y%m%d").to_string()),
+        schedule_relationship: Some(schedule_relationship as i32),
+        ..Default::default()
+    };
+    FeedEntity {
+        id: trip_id.to_string(),
+        trip_update: Some(TripUpdate { trip, stop_time_update, ..Default::default() }),
+        ..Default::default()
+    }
+}
+
+fn stop_time_update(stop_sequence: u32, schedule_relationship: StopTimeScheduleRelationship, departure: Option<StopTimeEvent>) -> StopTimeUpdate {
+    StopTimeUpdate {
+        stop_sequence: Some(stop_sequence),
+        departure,
+        schedule_relationship: Some(schedule_relationship as i32),
+        ..Default::default()
+    }
+}
+
+fn delays(index: &TransitIndex, entities: Vec<FeedEntity>) -> DelayOverlay {
+    let trip_updates: Vec<TripUpdate> = round_trip(entities).into_iter().filter_map(|entity| entity.trip_update).collect();
+    DelayOverlay::new(index, &trip_updates, index.calendar.now())
+}
+
+#[test]
+fn delays_hold_until_the_next_update() {
+    let index = index();
+    let delay = |seconds| Some(StopTimeEvent { delay: Some(seconds), time: None });
+    let delays = delays(&index, vec![trip_update("T1", TripScheduleRelationship::Scheduled, vec![
+        stop_time_update(2, StopTimeScheduleRelationship::Scheduled, delay(120)),
+    ])]);
+    let timetable = delays.timetable(&index, 0, date());
+
+    assert!(timetable.is_predicted(0) && !timetable.is_predicted(1));
+    assert_eq!(timetable.departure_at(0, 0), DEPARTURE);
+    assert_eq!(timetable.departure_at(0, 1), DEPARTURE + 300 + 120);
+    assert_eq!(timetable.arrival_at(0, 2), DEPARTURE + 600 + 120);
+    assert_eq!(timetable.departure_at(1, 1), DEPARTURE + 1500);
+}
+
+#[test]
+fn absolute_times_become_delays() {
+    let index = index();
+    let predicted = index.calendar.service_day_start(date()) + i64::from(DEPARTURE + 300 + 45);
+    let delays = delays(&index, vec![trip_update("T1", TripScheduleRelationship::Scheduled, vec![
+        stop_time_update(2, StopTimeScheduleRelationship::Scheduled, Some(StopTimeEvent { delay: None, time: Some(predicted) })),
+    ])]);
+
+    assert_eq!(delays.timetable(&index, 0, date()).departure_at(0, 1), DEPARTURE + 300 + 45);
+}
+
+#[test]
+fn cancelled_trips_are_not_boarded() {
+    let index = index();
+    let delays = delays(&index, vec![trip_update("T1", TripScheduleRelationship::Canceled, Vec::new())]);
+    let timetable = delays.timetable(&index, 0, date());
+
+    assert!((0..3).all(|position| !timetable.stops_at(0, position)));
+    assert_eq!(timetable.earliest_trip(0, DEPARTURE, |_| true), Some(1));
+    assert_eq!(timetable.latest_trip(2, DEPARTURE + 1200, |_| true), None);
+    assert_eq!(timetable.trips_departing_from(0, 0), vec![1]);
+}
+
+#[test]
+fn skipped_stops_are_neither_boarded_nor_left() {
+    let index = index();
+    let delays = delays(&index, vec![trip_update("T1", TripScheduleRelationship::Scheduled, vec![
+        stop_time_update(1, StopTimeScheduleRelationship::Scheduled, Some(StopTimeEvent { delay: Some(60), time: None })),
+        stop_time_update(2, StopTimeScheduleRelationship::Skipped, None),
+    ])]);
+    let timetable = delays.timetable(&index, 0, date());
+
+    assert!(timetable.stops_at(0, 0) && !timetable.stops_at(0, 1) && timetable.stops_at(0, 2));
+    assert_eq!(timetable.earliest_trip(1, DEPARTURE, |_| true), Some(1));
+    assert_eq!(timetable.latest_trip(1, DEPARTURE + 1200, |_| true), None);
+    // The delay of the previous stop still applies past the skipped one
+    assert_eq!(timetable.arrival_at(0, 2), DEPARTURE + 600 + 60);
+}
+
+#[test]
+fn updates_without_a_start_date_apply_to_the_running_trip() {
+    // The night trip leaves at 23:50 and arrives at 00:30 of the next day
+    let index = test_feed::index(&[
+        ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\nP1,First,48.10,17.10\nP2,Second,48.11,17.11\n"),
+        ("routes.txt", "route_id,route_short_name,route_type\nN,N,3\n"),
+        ("trips.txt", "route_id,service_id,trip_id\nN,WD,N1\n"),
+        ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\nN1,23:50:00,23:50:00,P1,1\nN1,24:30:00,24:30:00,P2,2\n"),
+    ]);
+    let trip_update = TripUpdate {
+        trip: TripDescriptor { trip_id: Some("N1".to_string()), ..Default::default() },
+        delay: Some(120),
+        ..Default::default()
+    };
+    let next_date = date().succ_opt().unwrap();
+    let updated_date = |hour, minute| {
+        let now = index.calendar.timezone.from_local_datetime(&next_date.and_hms_opt(hour, minute, 0).unwrap()).unwrap();
+        let delays = DelayOverlay::new(&index, std::slice::from_ref(&trip_update), now);
+        [date(), next_date].into_iter().find(|&date| delays.timetable(&index, 0, date).is_predicted(0))
+    };
+
+    // Shortly after midnight the trip of the previous day is on its way, later in the day the update is for the next one
+    assert_eq!(updated_date(0, 10), Some(date()));
+    assert_eq!(updated_date(10, 0), Some(next_date));
+}
+
+#[test]
+fn vehicles_are_matched_to_the_static_feed() {
+    let index = index();
+    let vehicle = VehiclePosition {
+        trip: Some(TripDescriptor { trip_id: Some("T2".to_string()), ..Default::default() }),
+        position: Some(Position { latitude: 48.105, longitude: 17.105, bearing: Some(45.0), speed: None }),
+        current_stop_sequence: Some(2),
+        current_status: Some(VehicleStopStatus::IncomingAt as i32),
+        vehicle: Some(VehicleDescriptor { id: Some("V1".to_string()), label: Some("7301".to_string()) }),
+        ..Default::default()
+    };
+    let positions: Vec<VehiclePosition> = round_trip(vec![FeedEntity { id: "V1".to_string(), vehicle: Some(vehicle), ..Default::default() }])
+        .into_iter()
+        .filter_map(|entity| entity.vehicle)
+        .collect();
+
+    let filter = VehicleFilter { bbox: None, route: Some("1"), trip_id: None };
+    let vehicles = find_vehicles(&index, &positions, &filter);
+    assert_eq!(vehicles.len(), 1);
+    assert_eq!((vehicles[0].id, vehicles[0].label, vehicles[0].trip_id), (Some("V1"), Some("7301"), Some("T2")));
+    assert_eq!(vehicles[0].current_stop.map(|stop| stop.id.as_str()), Some("P2"));
+    assert_eq!(vehicles[0].current_status, "incoming_at");
+
+    let outside = VehicleFilter { bbox: Some((17.11, 48.11, 17.12, 48.12)), route: None, trip_id: None };
+    assert!(find_vehicles(&index, &positions, &outside).is_empty());
+}
+
+#[test]
+fn alerts_match_their_entities_while_active() {
+    let text = |text: &str| Some(TranslatedString { translation: vec![Translation { text: text.to_string(), language: None }] });
+    let alert = Alert {
+        active_period: vec![TimeRange { start: Some(1000), end: Some(2000) }],
+        informed_entity: vec![EntitySelector { stop_id: Some("P2".to_string()), ..Default::default() }],
+        header_text: text("Stop closed"),
+        ..Default::default()
+    };
+    let alerts: Vec<ServiceAlert> = round_trip(vec![FeedEntity { id: "A1".to_string(), alert: Some(alert), ..Default::default() }])
+        .into_iter()
+        .filter_map(|entity| Some(ServiceAlert::new(entity.id, entity.alert?)))
+        .collect();
+    let alerts = ServiceAlerts::new(alerts);
+
+    let stop = InformedEntity { stop_ids: vec!["P1", "P2"], route_id: None, trip_id: None };
+    let other_stop = InformedEntity { stop_ids: vec!["P3"], route_id: None, trip_id: None };
+
+    let matching = alerts.matching(&stop, 1500);
+    assert_eq!(matching.len(), 1);
+    assert_eq!((matching[0].header.as_deref(), matching[0].cause, matching[0].effect), (Some("Stop closed"), "unknown_cause", "unknown_effect"));
+    assert!(alerts.matching(&stop, 2000).is_empty());
+    assert!(alerts.matching(&other_stop, 1500).is_empty());
+}
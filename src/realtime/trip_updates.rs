@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use chrono::{DateTime, NaiveDate};
+use chrono_tz::Tz;
+use crate::{
+    model::TripIdx,
+    transit_index::{Timetable, TransitIndex, TripPattern},
+};
+use super::proto::{StopTimeEvent, StopTimeScheduleRelationship, TripScheduleRelationship, TripUpdate};
+
+/// Stop times of a pattern with the delays of its updated trips applied
+pub struct RealtimeTimes {
+    /// (arrival, departure) at every stop of the pattern, trip after trip
+    pub stop_times: Vec<(u32, u32)>,
+    /// For every trip of the pattern, whether there is a realtime update for it
+    pub updated_trips: Vec<bool>,
+    /// Whether the vehicle does not stop at the stop of the pattern, trip after trip.
+    /// Cancelled trips stop nowhere
+    pub skipped_stops: Vec<bool>,
+    /// Whether the trips still do not overtake each other at any stop
+    pub sorted: bool,
+}
+
+/// Delay of a trip at one of its stops, in seconds
+#[derive(Clone, Copy)]
+struct StopDelay {
+    arrival: i32,
+    departure: i32,
+    /// The vehicle passes the stop without stopping
+    skipped: bool,
+}
+
+/// Delays from the TripUpdates feed, resolved against a single index. Updates refer to
+/// trips by their trip_id and to stops by their stop_sequence (or stop_id), which are
+/// matched to the trips and positions within the patterns of the index.
+/// Cancelled trips and skipped stops can be neither boarded nor left
+#[derive(Default)]
+pub struct DelayOverlay {
+    /// (pattern, service date) -> realtime times, only for patterns with an updated trip
+    patterns: HashMap<(usize, NaiveDate), RealtimeTimes>,
+}
+
+impl DelayOverlay {
+    /// Updates without a start date apply to the instance of the trip running at the time `now`
+    pub fn new(index: &TransitIndex, trip_updates: &[TripUpdate], now: DateTime<Tz>) -> Self {
+        let mut patterns: HashMap<(usize, NaiveDate), RealtimeTimes> = HashMap::new();
+
+        for update in trip_updates {
+            let Some(&trip_idx) = update.trip.trip_id.as_ref().and_then(|trip_id| index.trip_indices.get(trip_id)) else {
+                continue;
+            };
+            let Some((pattern_idx, trip_row)) = index.trip_patterns[trip_idx as usize] else {
+                continue;
+            };
+
+            let pattern = &index.patterns[pattern_idx];
+            let date = update.trip.start_date
+                .as_deref()
+                .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+                .unwrap_or_else(|| Self::running_service_date(index, trip_idx, pattern, trip_row, now));
+            let delays = Self::resolve_delays(index, update, trip_idx, pattern, trip_row, index.calendar.service_day_start(date));
+
+            let realtime = patterns.entry((pattern_idx, date)).or_insert_with(|| RealtimeTimes {
+                stop_times: (0..pattern.trips.len())
+                    .flat_map(|trip| (0..pattern.stops.len()).map(move |position| (trip, position)))
+                    .map(|(trip, position)| (pattern.arrival_at(trip, position), pattern.departure_at(trip, position)))
+                    .collect(),
+                updated_trips: vec![false; pattern.trips.len()],
+                skipped_stops: vec![false; pattern.trips.len() * pattern.stops.len()],
+                sorted: true,
+            });
+
+            let is_cancelled = matches!(
+                update.trip.schedule_relationship(),
+                TripScheduleRelationship::Canceled | TripScheduleRelationship::Deleted,
+            );
+
+            realtime.updated_trips[trip_row] = true;
+            delays.into_iter().enumerate().for_each(|(position, delay)| {
+                let arrival = pattern.arrival_at(trip_row, position).saturating_add_signed(delay.arrival);
+                let departure = pattern.departure_at(trip_row, position).saturating_add_signed(delay.departure).max(arrival);
+                realtime.stop_times[trip_row * pattern.stops.len() + position] = (arrival, departure);
+                realtime.skipped_stops[trip_row * pattern.stops.len() + position] = is_cancelled || delay.skipped;
+            });
+        }
+
+        patterns.iter_mut().for_each(|(&(pattern_idx, _), realtime)| {
+            let stop_count = index.patterns[pattern_idx].stops.len();
+            realtime.sorted = realtime.stop_times
+                .chunks(stop_count)
+                .zip(realtime.stop_times.chunks(stop_count).skip(1))
+                .all(|(previous, trip)| previous.iter().zip(trip).all(|(p, t)| t.0 >= p.0 && t.1 >= p.1));
+        });
+
+        DelayOverlay { patterns }
+    }
+
+    pub fn timetable<'a>(&'a self, index: &'a TransitIndex, pattern_idx: usize, date: NaiveDate) -> Timetable<'a> {
+        let pattern = &index.patterns[pattern_idx];
+
+        if self.patterns.is_empty() {
+            return pattern.timetable();
+        }
+
+        match self.patterns.get(&(pattern_idx, date)) {
+            Some(realtime) => Timetable::with_realtime(pattern, realtime),
+            None => pattern.timetable(),
+        }
+    }
+
+    /// Service date of the trip at the time: the day before while its instance of that day
+    /// is still on its way after midnight, otherwise the day itself
+    fn running_service_date(index: &TransitIndex, trip_idx: TripIdx, pattern: &TripPattern, trip_row: usize, now: DateTime<Tz>) -> NaiveDate {
+        let today = now.date_naive();
+        let previous_day = today.pred_opt().unwrap();
+        let last_arrival = pattern.arrival_at(trip_row, pattern.stops.len() - 1);
+
+        let is_running_from_previous_day = index.calendar.runs_on(index.trips[trip_idx as usize].service, previous_day)
+            && now.timestamp() <= index.calendar.service_day_start(previous_day) + i64::from(last_arrival);
+        if is_running_from_previous_day { previous_day } else { today }
+    }
+
+    /// Returns the delay at every stop of the trip. The delay of an update holds for the following
+    /// stops up to the next update, stops before the first update keep their schedule. Without
+    /// any stop time update the delay of the whole trip is used
+    fn resolve_delays(
+        index: &TransitIndex,
+        update: &TripUpdate,
+        trip_idx: TripIdx,
+        pattern: &TripPattern,
+        trip_row: usize,
+        service_day_start: i64,
+    ) -> Vec<StopDelay> {
+        let stop_count = pattern.stops.len();
+
+        if update.stop_time_update.is_empty() {
+            let delay = update.delay.unwrap_or(0);
+            return vec![StopDelay { arrival: delay, departure: delay, skipped: false }; stop_count];
+        }
+
+        // Updates are sorted by the stop sequence, so a stop id is looked up only after the previous update
+        let stop_sequences = &index.stop_sequences[trip_idx as usize];
+        let mut next_position = 0;
+        let mut positioned_updates = update.stop_time_update
+            .iter()
+            .filter_map(|stop_time_update| {
+                let position = match (stop_time_update.stop_sequence, &stop_time_update.stop_id) {
+                    (Some(stop_sequence), _) => stop_sequences.iter().position(|&s| u32::from(s) == stop_sequence)?,
+                    (None, Some(stop_id)) => (next_position..stop_count)
+                        .find(|&position| index.platforms[pattern.stops[position] as usize].id == *stop_id)?,
+                    (None, None) => return None,
+                };
+                next_position = position + 1;
+                Some((position, stop_time_update))
+            })
+            .peekable();
+
+        let event_delay = |event: &Option<StopTimeEvent>, scheduled: u32| {
+            event.as_ref().and_then(|event| {
+                event.delay.or_else(|| event.time.map(|time| (time - service_day_start - i64::from(scheduled)) as i32))
+            })
+        };
+
+        let mut propagated_delay = 0;
+
+        (0..stop_count)
+            .map(|position| {
+                let mut delays = StopDelay { arrival: propagated_delay, departure: propagated_delay, skipped: false };
+
+                while let Some((update_position, stop_time_update)) = positioned_updates.next_if(|(p, _)| *p <= position) {
+                    match stop_time_update.schedule_relationship() {
+                        StopTimeScheduleRelationship::Skipped => delays.skipped |= update_position == position,
+                        StopTimeScheduleRelationship::NoData => {
+                            propagated_delay = 0;
+                            delays = StopDelay { arrival: 0, departure: 0, skipped: false };
+                        },
+                        StopTimeScheduleRelationship::Scheduled | StopTimeScheduleRelationship::Unscheduled => {
+                            let arrival = event_delay(&stop_time_update.arrival, pattern.arrival_at(trip_row, position));
+                            let departure = event_delay(&stop_time_update.departure, pattern.departure_at(trip_row, position));
+
+                            if let Some(delay) = departure.or(arrival) {
+                                delays = StopDelay { arrival: arrival.unwrap_or(delay), departure: delay, skipped: false };
+                                propagated_delay = delay;
+                            }
+                        },
+                    }
+                }
+
+                delays
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{model::{Platform, Route, Trip, TripIdx}, transit_index::TransitIndex};
+use super::proto::{VehiclePosition, VehicleStopStatus};
+
+/// Criteria a listed vehicle has to match, all of them are optional
+pub struct VehicleFilter<'a> {
+    /// (min longitude, min latitude, max longitude, max latitude)
+    pub bbox: Option<(f64, f64, f64, f64)>,
+    /// Route short name or route_id
+    pub route: Option<&'a str>,
+    pub trip_id: Option<&'a str>,
+}
+
+/// Vehicle position matched to the trip, route and stop of the static feed
+pub struct Vehicle<'a> {
+    pub id: Option<&'a str>,
+    pub label: Option<&'a str>,
+    pub longitude: f64,
+    pub latitude: f64,
+    /// Degrees clockwise from the north
+    pub bearing: Option<f32>,
+    /// Meters per second
+    pub speed: Option<f32>,
+    pub trip_id: Option<&'a str>,
+    /// `None` when the trip is not in the static feed
+    pub trip: Option<&'a Trip>,
+    pub route: Option<&'a Route>,
+    pub current_stop: Option<&'a Platform>,
+    pub current_status: &'static str,
+    /// POSIX time the position was measured at
+    pub timestamp: Option<u64>,
+}
+
+/// Returns the vehicles matching the filter, vehicles without a position are skipped
+pub fn find_vehicles<'a>(index: &'a TransitIndex, positions: &'a [VehiclePosition], filter: &VehicleFilter) -> Vec<Vehicle<'a>> {
+    positions
+        .iter()
+        .filter_map(|position| match_vehicle(index, position))
+        .filter(|vehicle| {
+            filter.bbox.is_none_or(|(min_lon, min_lat, max_lon, max_lat)| {
+                (min_lon..=max_lon).contains(&vehicle.longitude) && (min_lat..=max_lat).contains(&vehicle.latitude)
+            })
+        })
+        .filter(|vehicle| {
+            filter.route.is_none_or(|route| {
+                vehicle.route.is_some_and(|r| r.id == route || r.short_name.as_deref() == Some(route))
+            })
+        })
+        .filter(|vehicle| filter.trip_id.is_none_or(|trip_id| vehicle.trip_id == Some(trip_id)))
+        .collect()
+}
+
+fn match_vehicle<'a>(index: &'a TransitIndex, vehicle: &'a VehiclePosition) -> Option<Vehicle<'a>> {
+    let position = vehicle.position.as_ref()?;
+    let trip_id = vehicle.trip.as_ref().and_then(|trip| trip.trip_id.as_deref());
+    let trip_idx = trip_id.and_then(|trip_id| index.trip_indices.get(trip_id)).copied();
+    let trip = trip_idx.map(|trip_idx| &index.trips[trip_idx as usize]);
+
+    let route = trip
+        .map(|trip| &index.routes[trip.route as usize])
+        .or_else(|| index.get_route_by_id(vehicle.trip.as_ref()?.route_id.as_deref()?));
+
+    // The stop id is preferred, the stop sequence needs the trip to be known
+    let current_stop = match (&vehicle.stop_id, vehicle.current_stop_sequence, trip_idx) {
+        (Some(stop_id), _, _) => index.get_platform_by_id(stop_id),
+        (None, Some(stop_sequence), Some(trip_idx)) => stop_of_trip(index, trip_idx, stop_sequence),
+        _ => None,
+    };
+
+    Some(Vehicle {
+        id: vehicle.vehicle.as_ref().and_then(|v| v.id.as_deref()),
+        label: vehicle.vehicle.as_ref().and_then(|v| v.label.as_deref()),
+        longitude: f64::from(position.longitude),
+        latitude: f64::from(position.latitude),
+        bearing: position.bearing,
+        speed: position.speed,
+        trip_id,
+        trip,
+        route,
+        current_stop,
+        current_status: match vehicle.current_status() {
+            VehicleStopStatus::IncomingAt => "incoming_at",
+            VehicleStopStatus::StoppedAt => "stopped_at",
+            VehicleStopStatus::InTransitTo => "in_transit_to",
+        },
+        timestamp: vehicle.timestamp,
+    })
+}
+
+fn stop_of_trip(index: &TransitIndex, trip_idx: TripIdx, stop_sequence: u32) -> Option<&Platform> {
+    let (pattern_idx, _) = index.trip_patterns[trip_idx as usize]?;
+    let position = index.stop_sequences[trip_idx as usize].iter().position(|&s| u32::from(s) == stop_sequence)?;
+    Some(&index.platforms[index.patterns[pattern_idx].stops[position] as usize])
+}
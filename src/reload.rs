@@ -0,0 +1,281 @@
+//! Loading of the index on start and while the server runs. Requests take their own
+//! reference to the current index, so a reload only swaps the pointer and requests
+//! which are already being handled finish with the index they started with
+
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc, RwLock},
+    thread,
+    time::Duration,
+};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::{config::{Config, FeedContents}, snapshot, transit_index::TransitIndex};
+
+/// Changes of the feed closer to each other than this are handled by a single reload,
+/// copying a large archive usually produces a burst of events
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(2);
+
+pub struct SharedIndex {
+    current: RwLock<Arc<TransitIndex>>,
+    reloading: AtomicBool,
+    /// The feed changed while a reload was running, so it has to run once more
+    pending: AtomicBool,
+    config: Arc<Config>,
+}
+
+impl SharedIndex {
+    pub fn new(transit_index: TransitIndex, config: Arc<Config>) -> Self {
+        SharedIndex {
+            current: RwLock::new(Arc::new(transit_index)),
+            reloading: AtomicBool::new(false),
+            pending: AtomicBool::new(false),
+            config,
+        }
+    }
+
+    pub fn get(&self) -> Arc<TransitIndex> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Builds a new index from the configured feed in the background and swaps it in once it
+    /// is done. Returns `false` without doing anything when a reload is already running
+    pub fn reload(self: &Arc<Self>) -> bool {
+        if self.reloading.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+
+        let shared_index = self.clone();
+        thread::spawn(move || loop {
+            shared_index.pending.store(false, Ordering::SeqCst);
+            println!("[i] Reloading the index from {}", shared_index.config.feed);
+            match load_index(&shared_index.config) {
+                Ok(transit_index) => {
+                    *shared_index.current.write().unwrap() = Arc::new(transit_index);
+                    println!("[i] Index reloaded");
+                },
+                Err(e) => eprintln!("[!] Could not reload the index, keeping the current one: {}", e),
+            }
+            shared_index.reloading.store(false, Ordering::SeqCst);
+
+            // A change requested during the reload is picked up by one more reload,
+            // unless a new one was started in the meantime
+            if !shared_index.pending.load(Ordering::SeqCst) || shared_index.reloading.swap(true, Ordering::SeqCst) {
+                break;
+            }
+        });
+
+        true
+    }
+
+    /// Like `reload`, but when a reload is already running the index is reloaded
+    /// once more after it finishes, as it may have read the feed before the change
+    pub fn reload_after_change(self: &Arc<Self>) {
+        self.pending.store(true, Ordering::SeqCst);
+        if !self.reload() {
+            println!("[i] Reload already in progress, reloading again once it finishes");
+        }
+    }
+
+    /// Reloads the index whenever the feed at the path changes. The watcher stops once it is dropped
+    pub fn watch(self: &Arc<Self>, path: &Path) -> notify::Result<RecommendedWatcher> {
+        let path = path.canonicalize()?;
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+
+        // An archive is usually replaced rather than modified, so its directory is watched instead
+        let file_name = if path.is_dir() {
+            watcher.watch(&path, RecursiveMode::Recursive)?;
+            None
+        } else {
+            watcher.watch(path.parent().unwrap_or(Path::new("/")), RecursiveMode::NonRecursive)?;
+            path.file_name().map(|name| name.to_os_string())
+        };
+
+        let shared_index = self.clone();
+        thread::spawn(move || {
+            let is_feed_change = |event: &notify::Event| {
+                !matches!(event.kind, EventKind::Access(_))
+                    && file_name.as_ref().is_none_or(|name| event.paths.iter().any(|p| p.file_name() == Some(name)))
+            };
+
+            while let Ok(event) = receiver.recv() {
+                if !event.as_ref().is_ok_and(is_feed_change) {
+                    continue;
+                }
+
+                while receiver.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+
+                println!("[i] GTFS feed changed");
+                shared_index.reload_after_change();
+            }
+        });
+
+        Ok(watcher)
+    }
+}
+
+/// Admin endpoints need the bearer token when one is configured, otherwise they are only open to loopback
+pub fn is_authorized(admin_token: Option<&str>, remote_addr: Option<&SocketAddr>, authorization: Option<&str>) -> bool {
+    match admin_token {
+        Some(token) => authorization.and_then(|value| value.strip_prefix("Bearer ")) == Some(token),
+        None => remote_addr.is_some_and(|addr| addr.ip().is_loopback()),
+    }
+}
+
+/// Loads the index snapshot, if one is configured and was built from the same feed
+/// with the same walking and transfer options. Otherwise builds the index and rewrites the snapshot
+pub fn load_index(config: &Config) -> Result<TransitIndex, String> {
+    println!("[i] Loading GTFS feed from {}", config.feed);
+    let feed = config.feed.fetch()?;
+    let feed_checksum = feed.checksum()?;
+
+    let Some(path) = &config.index_path else {
+        return build_index(&feed, config);
+    };
+
+    match snapshot::read(path, feed_checksum) {
+        Ok(transit_index) if transit_index.walking == config.walking && transit_index.transfers == config.transfers => {
+            println!("[i] Index loaded from snapshot {}", path.display());
+            return Ok(transit_index);
+        },
+        Ok(_) => println!("[i] Snapshot {} was built with different walking or transfer options, rebuilding", path.display()),
+        Err(e) => println!("[i] Could not use snapshot {}: {}, rebuilding", path.display(), e),
+    }
+
+    let transit_index = build_index(&feed, config)?;
+    match snapshot::write(path, &transit_index, feed_checksum) {
+        Ok(()) => println!("[i] Index snapshot written to {}", path.display()),
+        Err(e) => eprintln!("[!] Could not write index snapshot to {}: {}", path.display(), e),
+    }
+    Ok(transit_index)
+}
+
+pub fn build_index(feed: &FeedContents, config: &Config) -> Result<TransitIndex, String> {
+    let gtfs = feed.parse().map_err(|e| format!("Could not parse the GTFS feed: {}", e))?;
+    Ok(TransitIndex::new(&gtfs, config.walking, &config.transfers))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::{Path, PathBuf}, sync::{atomic::Ordering, Arc}, thread, time::{Duration, Instant}};
+    use crate::{config::{Config, FeedSource}, routing::{TransferOptions, WalkingOptions}, test_feed};
+    use super::{is_authorized, load_index, SharedIndex};
+
+    const STOPS: &str = "stop_id,stop_name,stop_lat,stop_lon\nA,Alpha,48.1,17.1\nB,Beta,48.11,17.1\n";
+    const RENAMED_STOPS: &str = "stop_id,stop_name,stop_lat,stop_lon\nA,Alpha,48.1,17.1\nB,Gamma,48.11,17.1\n";
+
+    fn feed() -> PathBuf {
+        test_feed::write(&[
+            ("stops.txt", STOPS),
+            ("routes.txt", "route_id,route_short_name,route_type\nR,1,3\n"),
+            ("trips.txt", "route_id,service_id,trip_id\nR,WD,T\n"),
+            ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\nT,08:00:00,08:00:00,A,1\nT,08:05:00,08:05:00,B,2\n"),
+        ])
+    }
+
+    fn shared_index(feed: &Path) -> Arc<SharedIndex> {
+        let config = Config {
+            command: None,
+            feed: FeedSource::Path(feed.to_path_buf()),
+            index_path: None,
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            max_transfers: 5,
+            walking: WalkingOptions { radius: 300.0, speed: 1.4 },
+            transfers: TransferOptions {
+                min_change_time: 60,
+                mode_change_times: Default::default(),
+                station_change_times: Default::default(),
+            },
+            workers: 1,
+            trip_updates: None,
+            vehicle_positions: None,
+            service_alerts: None,
+            realtime_interval: 30,
+            admin_token: None,
+        };
+        let transit_index = load_index(&config).unwrap();
+        Arc::new(SharedIndex::new(transit_index, Arc::new(config)))
+    }
+
+    fn has_stop(shared_index: &SharedIndex, name: &str) -> bool {
+        shared_index.get().find_stop(name).is_some_and(|stop| stop.stop_name == name)
+    }
+
+    /// Waits until no reload is running or pending, reloads run in their own thread
+    fn wait_for_reloads(shared_index: &SharedIndex) {
+        let start = Instant::now();
+        while shared_index.reloading.load(Ordering::SeqCst) || shared_index.pending.load(Ordering::SeqCst) {
+            assert!(start.elapsed() < Duration::from_secs(20), "reload did not finish");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn swaps_in_the_reloaded_index() {
+        let feed = feed();
+        let shared_index = shared_index(&feed);
+        let before = shared_index.get();
+        fs::write(feed.join("stops.txt"), RENAMED_STOPS).unwrap();
+
+        // Holding the current index keeps the reload from finishing
+        let current = shared_index.current.read().unwrap();
+        assert!(shared_index.reload());
+        assert!(!shared_index.reload());
+        drop(current);
+
+        wait_for_reloads(&shared_index);
+        assert!(has_stop(&shared_index, "Gamma"));
+        assert!(before.stations.iter().all(|stop| stop.stop_name != "Gamma"));
+        fs::remove_dir_all(&feed).unwrap();
+    }
+
+    #[test]
+    fn reloads_again_after_a_change_during_a_reload() {
+        let feed = feed();
+        let shared_index = shared_index(&feed);
+
+        let current = shared_index.current.read().unwrap();
+        assert!(shared_index.reload());
+        fs::write(feed.join("stops.txt"), RENAMED_STOPS).unwrap();
+        shared_index.reload_after_change();
+        assert!(shared_index.pending.load(Ordering::SeqCst));
+        drop(current);
+
+        wait_for_reloads(&shared_index);
+        assert!(has_stop(&shared_index, "Gamma"));
+        fs::remove_dir_all(&feed).unwrap();
+    }
+
+    #[test]
+    fn reloads_when_the_watched_feed_changes() {
+        let feed = feed();
+        let shared_index = shared_index(&feed);
+        let _watcher = shared_index.watch(&feed).unwrap();
+
+        fs::write(feed.join("stops.txt"), RENAMED_STOPS).unwrap();
+
+        let start = Instant::now();
+        while !has_stop(&shared_index, "Gamma") {
+            assert!(start.elapsed() < Duration::from_secs(20), "feed change was not reloaded");
+            thread::sleep(Duration::from_millis(50));
+        }
+        fs::remove_dir_all(&feed).unwrap();
+    }
+
+    #[test]
+    fn authorizes_the_admin_token_or_loopback() {
+        let loopback = "127.0.0.1:40000".parse().unwrap();
+        let remote = "192.0.2.1:40000".parse().unwrap();
+
+        assert!(is_authorized(None, Some(&loopback), None));
+        assert!(!is_authorized(None, Some(&remote), None));
+        assert!(!is_authorized(None, None, None));
+
+        assert!(is_authorized(Some("secret"), Some(&remote), Some("Bearer secret")));
+        assert!(!is_authorized(Some("secret"), Some(&loopback), None));
+        assert!(!is_authorized(Some("secret"), Some(&loopback), Some("Bearer other")));
+        assert!(!is_authorized(Some("secret"), Some(&loopback), Some("secret")));
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use crate::{
+    calendar::{ServiceCalendar, ServiceTime},
+    model::{Mode, ModeFilter, StopIdx},
+    realtime::DelayOverlay,
+    transit_index::{DirectTrip, StopPlatforms},
+};
+
+pub mod csa;
+pub mod mc_raptor;
+pub mod raptor;
+
+#[cfg(test)]
+mod tests;
+
+/// A single journey planning request, expressed in platforms
+/// so the routers do not depend on how the stops were looked up
+pub struct RoutingQuery<'a> {
+    /// Platforms the journey can start from
+    pub sources: Vec<Access>,
+    /// Platforms the journey can end at
+    pub targets: Vec<Access>,
+    /// Service days whose trips are considered
+    pub days: [ServiceDay; 3],
+    /// Counted from the start of the first service day
+    pub time: TimeConstraint,
+    pub max_transfers: usize,
+    /// Only trips of routes with an allowed mode are boarded
+    pub modes: ModeFilter,
+    /// How many of the Pareto-optimal journeys to return, and which ones first
+    pub num_itineraries: usize,
+    pub sort: SortBy,
+    /// Realtime delays, trips are boarded at their predicted times
+    pub delays: &'a DelayOverlay,
+}
+
+/// Service day whose trips a search can board. Times of a search count from the start of
+/// the day before the requested date, so the trips of every day are shifted by its offset
+pub struct ServiceDay {
+    pub date: NaiveDate,
+    /// Seconds from the start of the first service day of the search
+    pub offset: u32,
+    /// A flag for every service, telling whether it runs on the date
+    pub active_services: Vec<bool>,
+}
+
+impl ServiceDay {
+    /// The day before the date, whose trips running past midnight are still on their way, the date itself,
+    /// and the next day, whose trips continue the journeys which do not arrive before the date ends
+    pub fn around(calendar: &ServiceCalendar, date: NaiveDate) -> [ServiceDay; 3] {
+        let previous_date = date.pred_opt().unwrap();
+        let next_date = date.succ_opt().unwrap();
+        let offset = calendar.previous_day_length(date);
+        [
+            ServiceDay { date: previous_date, offset: 0, active_services: calendar.active_services(previous_date) },
+            ServiceDay { date, offset, active_services: calendar.active_services(date) },
+            ServiceDay { date: next_date, offset: offset + calendar.previous_day_length(next_date), active_services: calendar.active_services(next_date) },
+        ]
+    }
+}
+
+/// Restrictions of a journey planning request chosen by the client
+#[derive(Clone, Copy)]
+pub struct JourneyOptions {
+    pub max_transfers: usize,
+    pub modes: ModeFilter,
+    pub num_itineraries: usize,
+    pub sort: SortBy,
+    pub algorithm: Algorithm,
+}
+
+/// Router answering the request
+#[derive(Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Raptor,
+    /// Earliest arrival for a departure time only, with the scheduled times
+    Csa,
+}
+
+impl Algorithm {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "raptor" => Some(Algorithm::Raptor),
+            "csa" => Some(Algorithm::Csa),
+            _ => None,
+        }
+    }
+}
+
+/// Criterion the alternative journeys are ordered by, ties are broken by the other two
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortBy {
+    /// Earliest arrival, or for arrive-by queries the latest departure
+    Time,
+    Transfers,
+    Walking,
+}
+
+impl SortBy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "time" => Some(SortBy::Time),
+            "transfers" => Some(SortBy::Transfers),
+            "walking" => Some(SortBy::Walking),
+            _ => None,
+        }
+    }
+}
+
+/// Where a journey starts or ends
+#[derive(Clone, Copy)]
+pub enum Place<'a> {
+    Stop(&'a StopPlatforms),
+    Coordinates { longitude: f64, latitude: f64 },
+}
+
+/// Platform where a journey can start (or end), together with the walk
+/// from (or to) the requested place. Both are zero when the place is the stop itself
+#[derive(Clone, Copy)]
+pub struct Access {
+    pub stop: StopIdx,
+    /// Seconds
+    pub duration: u32,
+    /// Meters
+    pub distance: f64,
+}
+
+#[derive(Clone, Copy)]
+pub enum TimeConstraint {
+    /// Earliest departure, in seconds since midnight
+    DepartAt(u32),
+    /// Latest arrival, in seconds since midnight
+    ArriveBy(u32),
+    /// Every departure within the window, in seconds since midnight
+    DepartBetween(u32, u32),
+}
+
+impl TimeConstraint {
+    /// Converts every time of the constraint
+    pub fn map(self, f: impl Fn(u32) -> u32) -> Self {
+        match self {
+            TimeConstraint::DepartAt(time) => TimeConstraint::DepartAt(f(time)),
+            TimeConstraint::ArriveBy(time) => TimeConstraint::ArriveBy(f(time)),
+            TimeConstraint::DepartBetween(start, end) => TimeConstraint::DepartBetween(f(start), f(end)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WalkingOptions {
+    /// Maximum distance in meters between two stops connected by a footpath
+    pub radius: f64,
+    /// Meters per second
+    pub speed: f64,
+}
+
+impl WalkingOptions {
+    pub fn duration(&self, distance: f64) -> u32 {
+        (distance / self.speed).ceil() as u32
+    }
+}
+
+/// Minimum time in seconds between leaving one vehicle and boarding another one
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferOptions {
+    /// Used where nothing more specific is configured
+    pub min_change_time: u32,
+    /// Used at the platforms served by the mode, indexed by the mode.
+    /// The longest one of all modes serving a platform wins
+    pub mode_change_times: [Option<u32>; Mode::ALL.len()],
+    /// Used at all platforms of the stop with the name, over the ones of the modes
+    pub station_change_times: HashMap<String, u32>,
+}
+
+/// Walk between two platforms, either from transfers.txt or to a platform within the walking radius
+#[derive(Serialize, Deserialize)]
+pub struct Footpath {
+    pub from_stop: StopIdx,
+    pub to_stop: StopIdx,
+    /// Seconds
+    pub duration: u32,
+    /// Meters
+    pub distance: f64,
+    /// Timed transfer from transfers.txt, the next vehicle waits so no change time is needed
+    pub guaranteed: bool,
+}
+
+pub enum Leg<'a> {
+    Transit(DirectTrip<'a>),
+    Walk(Walk),
+}
+
+/// Walk between two platforms, or between a platform and the requested coordinates
+pub struct Walk {
+    /// `None` when walking from the requested coordinates
+    pub from_stop: Option<StopIdx>,
+    /// `None` when walking to the requested coordinates
+    pub to_stop: Option<StopIdx>,
+    /// The times count from the start of the service day
+    pub service_date: NaiveDate,
+    pub departure_time: u32,
+    pub arrival_time: u32,
+    /// Meters
+    pub distance: f64,
+}
+
+impl Walk {
+    pub fn get_duration(&self) -> u32 {
+        self.arrival_time - self.departure_time
+    }
+}
+
+impl<'a> Leg<'a> {
+    pub fn get_departure_time(&self) -> ServiceTime {
+        match self {
+            Leg::Transit(trip) => trip.get_predicted_departure_time().unwrap_or(trip.get_departure_time()),
+            Leg::Walk(walk) => ServiceTime { date: walk.service_date, seconds: walk.departure_time },
+        }
+    }
+
+    pub fn get_arrival_time(&self) -> ServiceTime {
+        match self {
+            Leg::Transit(trip) => trip.get_predicted_arrival_time().unwrap_or(trip.get_arrival_time()),
+            Leg::Walk(walk) => ServiceTime { date: walk.service_date, seconds: walk.arrival_time },
+        }
+    }
+}
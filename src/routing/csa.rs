@@ -0,0 +1,286 @@
+//! Connection Scan Algorithm, see
+//! Dibbelt, Pajor, Strasser, Wagner: Intriguingly Simple and Fast Transit Routing (2013).
+//!
+//! Every trip is split into elementary connections between two consecutive stops,
+//! kept in a single array sorted by the departure. A query scans the connections
+//! departing after the requested time once, in that order: a connection is usable
+//! when its trip was already boarded or its stop was reached before it departs, and
+//! then it may improve the arrival at its next stop and the stops a footpath away.
+//!
+//! Connections of the day before the requested date still running after midnight,
+//! and of the day after it, are scanned too, merged with the ones of the date by
+//! their shifted departure.
+//!
+//! A trip is boarded no sooner than the minimum change time of the platform after
+//! a vehicle reached it, so the arrivals without any vehicle, from which the first trip
+//! is boarded right away, are kept apart. A vehicle continuing as the next trip of its
+//! block is stayed on without any change time once the previous trip was boarded.
+//!
+//! Only the earliest arrival for a departure time is answered, with the scheduled
+//! times. The number of transfers is not limited.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::{model::{StopIdx, TripIdx}, transit_index::{TransitIndex, TripPattern}};
+use super::{raptor::{build_legs, DayTrip, Direction, Segment}, Leg, RoutingQuery, ServiceDay, TimeConstraint};
+
+/// Whether the stop was reached without any vehicle
+type FromOrigin = bool;
+
+/// Ride of a trip from one stop to the next one
+#[derive(Serialize, Deserialize)]
+pub struct Connection {
+    pub pattern: u32,
+    /// Position of the trip within the pattern
+    pub trip: u32,
+    /// Position of the departure stop within the pattern, the arrival stop follows it
+    pub position: u32,
+    pub from_stop: StopIdx,
+    pub to_stop: StopIdx,
+    pub departure: u32,
+    pub arrival: u32,
+}
+
+/// Splits the trips of the patterns into connections, sorted by the departure
+pub fn build_connections(patterns: &[TripPattern]) -> Vec<Connection> {
+    let mut connections: Vec<Connection> = patterns
+        .iter()
+        .enumerate()
+        .flat_map(|(pattern_idx, pattern)| {
+            (0..pattern.trips.len()).flat_map(move |trip_idx| {
+                pattern.stops.windows(2).enumerate().map(move |(position, stops)| Connection {
+                    pattern: pattern_idx as u32,
+                    trip: trip_idx as u32,
+                    position: position as u32,
+                    from_stop: stops[0],
+                    to_stop: stops[1],
+                    departure: pattern.departure_at(trip_idx, position),
+                    arrival: pattern.arrival_at(trip_idx, position + 1),
+                })
+            })
+        })
+        .collect();
+
+    connections.sort_by_key(|connection| (connection.departure, connection.arrival));
+    connections
+}
+
+/// How a stop was reached
+#[derive(Clone, Copy)]
+enum Arrival {
+    /// The stop the search started from
+    Origin,
+    /// Walk from a stop the search started from
+    OriginWalk(usize),
+    /// (service day, connection the trip was boarded at, connection the trip was left after,
+    /// whether the boarding stop was reached without any vehicle)
+    Trip(usize, usize, usize, FromOrigin),
+    Walk(usize),
+}
+
+/// How a trip of a service day was boarded
+#[derive(Clone, Copy)]
+struct Boarding {
+    /// Connection the trip was boarded at
+    connection: usize,
+    /// Whether the trip, or the first trip of the block ridden before it, was boarded without any vehicle
+    from_origin: FromOrigin,
+    /// Previous trip of the block, when staying seated as the vehicle continues as this trip
+    continued_from: Option<TripIdx>,
+}
+
+struct Search<'a> {
+    index: &'a TransitIndex,
+    /// Platforms where the search ends, with the walk still needed after reaching them
+    destinations: HashMap<StopIdx, u32>,
+    /// Earliest arrival at every platform by a vehicle, or by a walk after one, and how it was reached
+    arrivals: Vec<Option<(u32, Arrival)>>,
+    /// Earliest arrival at every platform without any vehicle
+    origin_arrivals: Vec<Option<(u32, Arrival)>>,
+    /// (arrival at the requested place, platform, whether it was reached without any vehicle) of the best journey
+    best_destination: Option<(u32, StopIdx, FromOrigin)>,
+    /// For every service day and trip, how the trip was boarded
+    boarded: [Vec<Option<Boarding>>; 3],
+}
+
+/// Finds the journey with the earliest arrival for a depart-at query
+pub fn find_journey<'a>(index: &'a TransitIndex, query: &RoutingQuery<'a>) -> Option<Vec<Leg<'a>>> {
+    let TimeConstraint::DepartAt(time) = query.time else {
+        return None;
+    };
+
+    let mut search = Search {
+        index,
+        destinations: query.targets.iter().map(|access| (access.stop, access.duration)).collect(),
+        arrivals: vec![None; index.platforms.len()],
+        origin_arrivals: vec![None; index.platforms.len()],
+        best_destination: None,
+        boarded: std::array::from_fn(|_| vec![None; index.trips.len()]),
+    };
+
+    query.sources.iter().for_each(|access| {
+        search.reach(access.stop, time + access.duration, Arrival::Origin);
+    });
+    query.sources.iter().for_each(|access| search.relax_footpaths(access.stop, true));
+
+    for (day_idx, connection_idx) in scan_order(index, &query.days, time) {
+        let day = &query.days[day_idx];
+        let connection = &index.connections[connection_idx];
+        let (departure, arrival) = (day.offset + connection.departure, day.offset + connection.arrival);
+
+        // Connections departing after the best arrival can not improve it
+        if search.best_destination.is_some_and(|(best, _, _)| departure >= best) {
+            break;
+        }
+
+        let pattern = &index.patterns[connection.pattern as usize];
+        let trip_idx = pattern.trips[connection.trip as usize] as usize;
+
+        if search.boarded[day_idx][trip_idx].is_none() {
+            if !day.active_services[pattern.services[connection.trip as usize] as usize]
+                || !query.modes.allows(index.routes[pattern.route as usize].mode)
+            {
+                continue;
+            }
+
+            let Some(boarding) = search.boarding(day_idx, connection_idx, trip_idx, departure) else {
+                continue;
+            };
+            search.boarded[day_idx][trip_idx] = Some(boarding);
+        }
+
+        let Boarding { connection: boarded_at, from_origin, .. } = search.boarded[day_idx][trip_idx].unwrap();
+        if search.reach(connection.to_stop, arrival, Arrival::Trip(day_idx, boarded_at, connection_idx, from_origin)) {
+            search.relax_footpaths(connection.to_stop, false);
+        }
+    }
+
+    let (_, stop, from_origin) = search.best_destination?;
+    Some(search.reconstruct(query, stop, from_origin))
+}
+
+/// (service day, connection) of the connections of every day departing at the time or later,
+/// ordered by their departure in the times of the search
+fn scan_order<'d>(index: &'d TransitIndex, days: &'d [ServiceDay; 3], time: u32) -> impl Iterator<Item = (usize, usize)> + 'd {
+    let connections = &index.connections;
+    let mut next = days.each_ref().map(|day| {
+        connections.partition_point(|connection| day.offset + connection.departure < time)
+    });
+
+    std::iter::from_fn(move || {
+        let day_idx = (0..days.len())
+            .filter(|&day_idx| next[day_idx] < connections.len())
+            .min_by_key(|&day_idx| days[day_idx].offset + connections[next[day_idx]].departure)?;
+
+        next[day_idx] += 1;
+        Some((day_idx, next[day_idx] - 1))
+    })
+}
+
+impl<'a> Search<'a> {
+    /// Records the arrival at the stop unless it was reached earlier already
+    fn reach(&mut self, stop: StopIdx, time: u32, arrival: Arrival) -> bool {
+        let from_origin = matches!(arrival, Arrival::Origin | Arrival::OriginWalk(_));
+        let arrivals = if from_origin { &mut self.origin_arrivals } else { &mut self.arrivals };
+
+        if arrivals[stop as usize].is_some_and(|(best, _)| best <= time) {
+            return false;
+        }
+        arrivals[stop as usize] = Some((time, arrival));
+
+        // Journeys without any trip or footpath are not planned
+        if let (Some(&duration), false) = (self.destinations.get(&stop), matches!(arrival, Arrival::Origin)) {
+            if self.best_destination.is_none_or(|(best, _, _)| time + duration < best) {
+                self.best_destination = Some((time + duration, stop, from_origin));
+            }
+        }
+        true
+    }
+
+    /// Walks from a stop reached by a trip, or from an origin, to the stops connected by a footpath.
+    /// Walking arrivals are not relaxed again, so two footpaths are never chained
+    fn relax_footpaths(&mut self, stop: StopIdx, from_origin: FromOrigin) {
+        let index = self.index;
+        let arrivals = if from_origin { &self.origin_arrivals } else { &self.arrivals };
+        let Some((time, _)) = arrivals[stop as usize] else {
+            return;
+        };
+
+        for &footpath_idx in &index.outgoing_footpaths[stop as usize] {
+            let footpath = &index.footpaths[footpath_idx];
+            let arrival = if from_origin { Arrival::OriginWalk(footpath_idx) } else { Arrival::Walk(footpath_idx) };
+            self.reach(footpath.to_stop, time + footpath.duration, arrival);
+        }
+    }
+
+    /// Whether the trip of the connection can be boarded at its departure, keeping to the minimum change time
+    /// after a vehicle. A trip is also boarded when the vehicle continues as it after the previous trip of
+    /// its block was ridden to its end, whichever vehicle reached the platform first
+    fn boarding(&self, day_idx: usize, connection_idx: usize, trip_idx: usize, departure: u32) -> Option<Boarding> {
+        let index = self.index;
+        let connection = &index.connections[connection_idx];
+        let stop = connection.from_stop as usize;
+
+        let previous_trip = index.block_predecessors[trip_idx].filter(|_| connection.position == 0);
+        if let Some((previous_trip, previous)) = previous_trip.and_then(|trip| Some((trip, self.boarded[day_idx][trip as usize]?))) {
+            return Some(Boarding { connection: connection_idx, from_origin: previous.from_origin, continued_from: Some(previous_trip) });
+        }
+
+        if self.origin_arrivals[stop].is_some_and(|(time, _)| time <= departure) {
+            return Some(Boarding { connection: connection_idx, from_origin: true, continued_from: None });
+        }
+
+        let (time, arrival) = self.arrivals[stop]?;
+        let change_time = match arrival {
+            Arrival::Walk(footpath_idx) if index.footpaths[footpath_idx].guaranteed => Some(0),
+            _ => index.change_times[stop],
+        }?;
+
+        (time + change_time <= departure).then_some(Boarding { connection: connection_idx, from_origin: false, continued_from: None })
+    }
+
+    fn reconstruct(&self, query: &RoutingQuery<'a>, stop: StopIdx, from_origin: FromOrigin) -> Vec<Leg<'a>> {
+        let index = self.index;
+        let mut chain = Vec::new();
+        let mut current = (stop, from_origin);
+
+        loop {
+            let (stop, from_origin) = current;
+            let arrivals = if from_origin { &self.origin_arrivals } else { &self.arrivals };
+            let Some((mut time, arrival)) = arrivals[stop as usize] else {
+                break;
+            };
+
+            let (segment, previous_from_origin) = match arrival {
+                Arrival::Origin => break,
+                Arrival::OriginWalk(footpath_idx) => (Segment::Walk(footpath_idx), true),
+                Arrival::Walk(footpath_idx) => (Segment::Walk(footpath_idx), false),
+                Arrival::Trip(day, boarded_at, left_after, boarded_from_origin) => {
+                    let (boarding, leaving) = (&index.connections[boarded_at], &index.connections[left_after]);
+                    let mut segment = Segment::Transit(
+                        boarding.pattern as usize, DayTrip { day, trip: boarding.trip as usize }, boarding.position as usize, leaving.position as usize + 1,
+                    );
+
+                    // The previous trips of the block are ridden to their end before staying seated
+                    let mut trip_idx = index.patterns[boarding.pattern as usize].trips[boarding.trip as usize];
+                    while let Some(previous_trip) = self.boarded[day][trip_idx as usize].and_then(|boarding| boarding.continued_from) {
+                        chain.push((time, segment));
+
+                        let boarding = &index.connections[self.boarded[day][previous_trip as usize].unwrap().connection];
+                        let pattern = &index.patterns[boarding.pattern as usize];
+                        let last_position = pattern.stops.len() - 1;
+                        time = query.days[day].offset + pattern.arrival_at(boarding.trip as usize, last_position);
+                        segment = Segment::Transit(boarding.pattern as usize, DayTrip { day, trip: boarding.trip as usize }, boarding.position as usize, last_position);
+                        trip_idx = previous_trip;
+                    }
+                    (segment, boarded_from_origin)
+                },
+            };
+
+            chain.push((time, segment));
+            current = (segment.previous_stop(index, Direction::Forward), previous_from_origin);
+        }
+
+        build_legs(index, |pattern_idx, _| index.patterns[pattern_idx].timetable(), Direction::Forward, &chain, stop, query)
+    }
+}
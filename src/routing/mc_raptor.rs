@@ -0,0 +1,365 @@
+//! McRAPTOR, the multi-criteria variant of RAPTOR from the same paper.
+//!
+//! Instead of a single label, every stop keeps a bag of labels none of which is
+//! better than another one both in time and in the distance walked so far. The
+//! number of trips is given by the round, so the journeys found over all rounds
+//! are Pareto-optimal in time, transfers and walking distance.
+//!
+//! Trips are boarded keeping to the minimum change time the same way as RAPTOR does.
+//!
+//! A label is dropped when a label of the same stop from the same or an earlier
+//! round, or a journey already found, is at least as good in both criteria.
+
+use std::collections::{HashMap, HashSet};
+use crate::{model::{ModeFilter, StopIdx}, realtime::DelayOverlay, transit_index::TransitIndex};
+use super::{
+    raptor::{build_legs, in_seat_continuation, ready_time, scan_order, DayTimetables, DayTrip, Direction, Segment},
+    Leg, RoutingQuery, ServiceDay, SortBy, TimeConstraint,
+};
+
+#[derive(Clone, Copy)]
+struct Label {
+    stop: StopIdx,
+    /// Arrival at the stop for forward search, departure from the stop for backward search
+    time: u32,
+    /// Meters walked since the start of the search
+    walked: u32,
+    /// How the stop was reached, `None` for the stops the search started from
+    segment: Option<Segment>,
+    /// Label the segment was entered from
+    parent: Option<usize>,
+}
+
+/// Trip of the scanned pattern boarded (or for backward search alighted) from a label of the previous round
+#[derive(Clone, Copy)]
+struct RouteLabel {
+    trip: DayTrip,
+    fixed_position: usize,
+    walked: u32,
+    parent: usize,
+}
+
+/// (pattern, position the scan starts from, route bag of the trips already boarded there)
+type PatternScan = (usize, usize, Vec<RouteLabel>);
+
+#[derive(Clone, Copy)]
+struct Journey {
+    /// Arrival at the requested place, or for backward search departure from it
+    time: u32,
+    walked: u32,
+    /// Number of trips
+    round: usize,
+    label: usize,
+}
+
+struct Search<'a> {
+    index: &'a TransitIndex,
+    delays: &'a DelayOverlay,
+    modes: ModeFilter,
+    direction: Direction,
+    /// Platforms where the search ends, with the (duration, distance) of the walk still needed
+    destinations: HashMap<StopIdx, (u32, u32)>,
+    /// Every label created by the search, bags refer to them by their position
+    labels: Vec<Label>,
+    /// rounds[k] holds the bags of labels created in round k
+    rounds: Vec<HashMap<StopIdx, Vec<usize>>>,
+    /// Labels of every stop not dominated by any label of the rounds so far
+    best_bags: HashMap<StopIdx, Vec<usize>>,
+    marked_stops: HashSet<StopIdx>,
+    journeys: Vec<Journey>,
+}
+
+/// Finds the journeys not worse than any other one in all of time, number of transfers and
+/// walking distance, ordered by the criterion. At most `num_itineraries` of them are returned
+pub fn find_journeys<'a>(index: &'a TransitIndex, query: &RoutingQuery<'a>) -> Vec<Vec<Leg<'a>>> {
+    let (direction, time, origins, destinations) = match query.time {
+        TimeConstraint::DepartAt(time) | TimeConstraint::DepartBetween(time, _) => (Direction::Forward, time, &query.sources, &query.targets),
+        TimeConstraint::ArriveBy(time) => (Direction::Backward, time, &query.targets, &query.sources),
+    };
+
+    let mut search = Search {
+        index,
+        delays: query.delays,
+        modes: query.modes,
+        direction,
+        destinations: destinations.iter().map(|access| (access.stop, (access.duration, meters(access.distance)))).collect(),
+        labels: Vec::new(),
+        rounds: vec![HashMap::new()],
+        best_bags: HashMap::new(),
+        marked_stops: HashSet::new(),
+        journeys: Vec::new(),
+    };
+
+    origins.iter().for_each(|access| {
+        search.add(0, Label {
+            stop: access.stop,
+            time: direction.shift(time, access.duration),
+            walked: meters(access.distance),
+            segment: None,
+            parent: None,
+        })
+    });
+    search.relax_footpaths(0);
+
+    for round in 1..=query.max_transfers + 1 {
+        search.rounds.push(HashMap::new());
+        search.scan_patterns(round, &query.days);
+        search.relax_footpaths(round);
+
+        if search.marked_stops.is_empty() {
+            break;
+        }
+    }
+
+    search.pareto_journeys(query.sort)
+        .into_iter()
+        .take(query.num_itineraries)
+        .map(|journey| search.reconstruct(journey.label, query))
+        .collect()
+}
+
+/// Whether the (time, walked) criteria of `a` are at least as good as the ones of `b`
+fn dominates(direction: Direction, a: (u32, u32), b: (u32, u32)) -> bool {
+    !direction.is_better(b.0, Some(a.0)) && a.1 <= b.1
+}
+
+fn meters(distance: f64) -> u32 {
+    distance.round() as u32
+}
+
+impl<'a> Search<'a> {
+    fn add(&mut self, round: usize, label: Label) {
+        let direction = self.direction;
+        let criteria = (label.time, label.walked);
+        let labels = &self.labels;
+
+        let is_dominated = self.best_bags
+            .get(&label.stop)
+            .is_some_and(|bag| bag.iter().any(|&other| dominates(direction, (labels[other].time, labels[other].walked), criteria)))
+            || self.journeys.iter().any(|journey| dominates(direction, (journey.time, journey.walked), criteria));
+
+        if is_dominated {
+            return;
+        }
+
+        let label_idx = self.labels.len();
+        self.labels.push(label);
+        let labels = &self.labels;
+        let is_worse = |&other: &usize| !dominates(direction, criteria, (labels[other].time, labels[other].walked));
+
+        let best_bag = self.best_bags.entry(label.stop).or_default();
+        best_bag.retain(is_worse);
+        best_bag.push(label_idx);
+
+        let bag = self.rounds[round].entry(label.stop).or_default();
+        bag.retain(is_worse);
+        bag.push(label_idx);
+
+        self.marked_stops.insert(label.stop);
+
+        // Journeys without any trip or footpath are not planned
+        if let (Some(&(duration, distance)), Some(_)) = (self.destinations.get(&label.stop), label.segment) {
+            self.journeys.push(Journey {
+                time: direction.shift(label.time, duration),
+                walked: label.walked + distance,
+                round,
+                label: label_idx,
+            });
+        }
+    }
+
+    fn scan_patterns(&mut self, round: usize, days: &[ServiceDay; 3]) {
+        let index = self.index;
+        let direction = self.direction;
+        let modes = self.modes;
+
+        let mut queue: HashMap<usize, usize> = HashMap::new();
+        self.marked_stops.drain().for_each(|stop| {
+            index.stop_patterns[stop as usize].iter().for_each(|&(pattern_idx, position)| {
+                if !modes.allows(index.routes[index.patterns[pattern_idx].route as usize].mode) {
+                    return;
+                }
+
+                let entry = queue.entry(pattern_idx).or_insert(position);
+                *entry = direction.best(*entry, position);
+            });
+        });
+
+        // Patterns the vehicles continue as are scanned within the same round
+        let mut scans: Vec<PatternScan> = queue
+            .into_iter()
+            .map(|(pattern_idx, start_position)| (pattern_idx, start_position, Vec::new()))
+            .collect();
+
+        while let Some((pattern_idx, start_position, route_bag)) = scans.pop() {
+            let continuations = self.scan_pattern(round, days, pattern_idx, start_position, route_bag);
+            scans.extend(continuations);
+        }
+    }
+
+    /// Scans the pattern from the position, riding the trips of the route bag already boarded there.
+    /// Returns the scans of the patterns the vehicles of the trips reaching the end continue as
+    fn scan_pattern(
+        &mut self,
+        round: usize,
+        days: &[ServiceDay; 3],
+        pattern_idx: usize,
+        start_position: usize,
+        mut route_bag: Vec<RouteLabel>,
+    ) -> Vec<PatternScan> {
+        let index = self.index;
+        let direction = self.direction;
+        let timetables = DayTimetables::new(index, self.delays, days, pattern_idx);
+        let pattern = timetables.pattern;
+
+        for position in scan_order(direction, pattern, start_position) {
+            let stop = pattern.stops[position];
+            let is_ridden = |route_label: &&RouteLabel| route_label.fixed_position != position && timetables.stops_at(route_label.trip, position);
+
+            for route_label in route_bag.iter().filter(is_ridden) {
+                let (time, segment) = match direction {
+                    Direction::Forward => (timetables.arrival_at(route_label.trip, position), Segment::Transit(pattern_idx, route_label.trip, route_label.fixed_position, position)),
+                    Direction::Backward => (timetables.departure_at(route_label.trip, position), Segment::Transit(pattern_idx, route_label.trip, position, route_label.fixed_position)),
+                };
+
+                self.add(round, Label { stop, time, walked: route_label.walked, segment: Some(segment), parent: Some(route_label.parent) });
+            }
+
+            // Every label of the previous round boards its own earliest (or latest) trip
+            let Some(bag) = self.rounds[round - 1].get(&stop) else {
+                continue;
+            };
+
+            // Trips are compared by their times at this stop, delayed trips can overtake each other
+            let boarding_time = |trip: DayTrip| timetables.boarding_time(direction, trip, position);
+
+            for &label_idx in bag {
+                let label = &self.labels[label_idx];
+                let Some(trip) = ready_time(index, direction, stop, label.time, label.segment, round - 1)
+                    .and_then(|time| timetables.find_trip(direction, position, time))
+                else {
+                    continue;
+                };
+
+                let criteria = (boarding_time(trip), label.walked);
+                if route_bag.iter().any(|other| dominates(direction, (boarding_time(other.trip), other.walked), criteria)) {
+                    continue;
+                }
+
+                route_bag.retain(|other| !dominates(direction, criteria, (boarding_time(other.trip), other.walked)));
+                route_bag.push(RouteLabel { trip, fixed_position: position, walked: label.walked, parent: label_idx });
+            }
+        }
+
+        let end_position = direction.last_position(pattern);
+        route_bag
+            .into_iter()
+            .filter_map(|route_label| {
+                let (next_pattern, next_trip, next_position) = in_seat_continuation(
+                    index, self.delays, self.modes, direction, &timetables, route_label.trip, route_label.fixed_position,
+                )?;
+
+                // The segment ridden to the end is kept outside of the bags, only as the parent of the continuation
+                let (time, segment) = match direction {
+                    Direction::Forward => (timetables.arrival_at(route_label.trip, end_position), Segment::Transit(pattern_idx, route_label.trip, route_label.fixed_position, end_position)),
+                    Direction::Backward => (timetables.departure_at(route_label.trip, end_position), Segment::Transit(pattern_idx, route_label.trip, end_position, route_label.fixed_position)),
+                };
+                self.labels.push(Label {
+                    stop: pattern.stops[end_position],
+                    time,
+                    walked: route_label.walked,
+                    segment: Some(segment),
+                    parent: Some(route_label.parent),
+                });
+
+                let continuation = RouteLabel { trip: next_trip, fixed_position: next_position, walked: route_label.walked, parent: self.labels.len() - 1 };
+                Some((next_pattern, next_position, vec![continuation]))
+            })
+            .collect()
+    }
+
+    /// Walks from every label of the round which was not reached by walking to the stops
+    /// connected by a footpath, so two footpaths are never chained
+    fn relax_footpaths(&mut self, round: usize) {
+        let index = self.index;
+        let improved: Vec<usize> = self.marked_stops
+            .iter()
+            .filter_map(|stop| self.rounds[round].get(stop))
+            .flatten()
+            .copied()
+            .filter(|&label_idx| !matches!(self.labels[label_idx].segment, Some(Segment::Walk(_))))
+            .collect();
+
+        for label_idx in improved {
+            let label = self.labels[label_idx];
+            let footpaths = match self.direction {
+                Direction::Forward => &index.outgoing_footpaths[label.stop as usize],
+                Direction::Backward => &index.incoming_footpaths[label.stop as usize],
+            };
+
+            for &footpath_idx in footpaths {
+                let footpath = &index.footpaths[footpath_idx];
+                let other_stop = match self.direction {
+                    Direction::Forward => footpath.to_stop,
+                    Direction::Backward => footpath.from_stop,
+                };
+
+                self.add(round, Label {
+                    stop: other_stop,
+                    time: self.direction.shift(label.time, footpath.duration),
+                    walked: label.walked + meters(footpath.distance),
+                    segment: Some(Segment::Walk(footpath_idx)),
+                    parent: Some(label_idx),
+                });
+            }
+        }
+    }
+
+    /// Journeys not dominated in time, number of trips and walking distance by any other one,
+    /// of the journeys with equal criteria only the first one found is kept
+    fn pareto_journeys(&self, sort: SortBy) -> Vec<Journey> {
+        let direction = self.direction;
+        let is_dominated = |journey: &Journey, by: &Journey| {
+            dominates(direction, (by.time, by.walked), (journey.time, journey.walked)) && by.round <= journey.round
+        };
+
+        let mut journeys: Vec<Journey> = self.journeys
+            .iter()
+            .enumerate()
+            .filter(|&(i, journey)| {
+                self.journeys.iter().enumerate().all(|(j, other)| {
+                    let is_equal = (other.time, other.walked, other.round) == (journey.time, journey.walked, journey.round);
+                    i == j || !is_dominated(journey, other) || (is_equal && i < j)
+                })
+            })
+            .map(|(_, journey)| *journey)
+            .collect();
+
+        // Later departures come first for arrive-by queries
+        let time_key = |journey: &Journey| match direction {
+            Direction::Forward => i64::from(journey.time),
+            Direction::Backward => -i64::from(journey.time),
+        };
+
+        match sort {
+            SortBy::Time => journeys.sort_by_key(|journey| (time_key(journey), journey.round, journey.walked)),
+            SortBy::Transfers => journeys.sort_by_key(|journey| (journey.round, time_key(journey), journey.walked)),
+            SortBy::Walking => journeys.sort_by_key(|journey| (journey.walked, time_key(journey), journey.round)),
+        }
+        journeys
+    }
+
+    fn reconstruct(&self, label_idx: usize, query: &RoutingQuery<'a>) -> Vec<Leg<'a>> {
+        let end_stop = self.labels[label_idx].stop;
+        let mut chain = Vec::new();
+        let mut label = Some(self.labels[label_idx]);
+
+        while let Some(Label { time, segment: Some(segment), parent, .. }) = label {
+            chain.push((time, segment));
+            label = parent.map(|parent| self.labels[parent]);
+        }
+
+        let timetable = |pattern_idx: usize, date| self.delays.timetable(self.index, pattern_idx, date);
+        build_legs(self.index, timetable, self.direction, &chain, end_stop, query)
+    }
+}
@@ -0,0 +1,799 @@
+//! RAPTOR (Round-bAsed Public Transit Optimized Router), see
+//! Delling, Pajor, Werneck: Round-Based Public Transit Routing (2012).
+//!
+//! Every round scans the trip patterns passing through the stops improved in
+//! the previous round, then relaxes footpaths from the stops improved by those
+//! trips, so after round `k` the labels hold the earliest arrival at every stop
+//! using at most `k` trips.
+//!
+//! Arrive-by queries run the same rounds in reverse: labels hold the latest
+//! departure from every stop, which still reaches the target on time, and
+//! patterns are scanned from their last stop towards the first one.
+//!
+//! Trips of the day before the requested date can still run after midnight, and
+//! journeys late in the day can go on with the trips of the next day, so the trips of
+//! all three service days are boarded. Times of the search count from the start of
+//! the previous day, and the times of every trip are shifted by the offset of its day.
+//!
+//! Every trip but the first one of a journey is boarded no sooner than the minimum
+//! change time of its platform after the stop was reached, unless the walk there is
+//! a timed transfer. A vehicle continuing as the next trip of its block is ridden on
+//! within the same round, staying seated is no transfer.
+//!
+//! Walking to the first platform and from the last one is accounted for by
+//! shifting the labels of the origins and the times at the destinations.
+//!
+//! Range queries use rRAPTOR: the search runs once for every departure in the
+//! window, from the latest one to the earliest one, keeping the labels of the
+//! previous runs. A later departure reaching a stop just as early with as few
+//! trips makes the earlier one pointless there, so every run only explores what
+//! the later departures could not reach, and every destination label improved
+//! by a run is a journey not dominated by any journey leaving later.
+
+use std::collections::{HashMap, HashSet};
+use chrono::NaiveDate;
+use crate::{model::{ModeFilter, ServiceIdx, StopIdx}, realtime::DelayOverlay, transit_index::{Timetable, TransitIndex, TripPattern}};
+use super::{Access, Leg, RoutingQuery, ServiceDay, TimeConstraint, Walk};
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum Direction {
+    /// Earliest arrival for a given departure time
+    Forward,
+    /// Latest departure for a given arrival time
+    Backward,
+}
+
+impl Direction {
+    pub(super) fn is_better(self, time: u32, than: Option<u32>) -> bool {
+        match (self, than) {
+            (_, None) => true,
+            (Direction::Forward, Some(than)) => time < than,
+            (Direction::Backward, Some(than)) => time > than,
+        }
+    }
+
+    /// Moves the time by the duration in the direction of the search
+    pub(super) fn shift(self, time: u32, duration: u32) -> u32 {
+        match self {
+            Direction::Forward => time + duration,
+            Direction::Backward => time.saturating_sub(duration),
+        }
+    }
+
+    pub(super) fn best(self, a: usize, b: usize) -> usize {
+        match self {
+            Direction::Forward => a.min(b),
+            Direction::Backward => a.max(b),
+        }
+    }
+
+    pub(super) fn reversed(self) -> Self {
+        match self {
+            Direction::Forward => Direction::Backward,
+            Direction::Backward => Direction::Forward,
+        }
+    }
+
+    /// Position of the pattern where its scan starts
+    pub(super) fn first_position(self, pattern: &TripPattern) -> usize {
+        match self {
+            Direction::Forward => 0,
+            Direction::Backward => pattern.stops.len() - 1,
+        }
+    }
+
+    /// Position of the pattern where its scan ends
+    pub(super) fn last_position(self, pattern: &TripPattern) -> usize {
+        self.reversed().first_position(pattern)
+    }
+}
+
+/// Trip of a pattern on one of the service days of the search
+#[derive(Clone, Copy, PartialEq)]
+pub(super) struct DayTrip {
+    pub(super) day: usize,
+    /// Position of the trip within the pattern
+    pub(super) trip: usize,
+}
+
+/// Timetables of a pattern on the service days of the search,
+/// with the times shifted into the times of the search
+pub(super) struct DayTimetables<'t> {
+    days: &'t [ServiceDay; 3],
+    timetables: [Timetable<'t>; 3],
+    pub(super) pattern_idx: usize,
+    pub(super) pattern: &'t TripPattern,
+}
+
+impl<'t> DayTimetables<'t> {
+    pub(super) fn new(index: &'t TransitIndex, delays: &'t DelayOverlay, days: &'t [ServiceDay; 3], pattern_idx: usize) -> Self {
+        DayTimetables {
+            days,
+            timetables: days.each_ref().map(|day| delays.timetable(index, pattern_idx, day.date)),
+            pattern_idx,
+            pattern: &index.patterns[pattern_idx],
+        }
+    }
+
+    pub(super) fn arrival_at(&self, trip: DayTrip, position: usize) -> u32 {
+        self.timetables[trip.day].arrival_at(trip.trip, position) + self.days[trip.day].offset
+    }
+
+    pub(super) fn departure_at(&self, trip: DayTrip, position: usize) -> u32 {
+        self.timetables[trip.day].departure_at(trip.trip, position) + self.days[trip.day].offset
+    }
+
+    /// Departure for forward search, arrival for backward search
+    pub(super) fn boarding_time(&self, direction: Direction, trip: DayTrip, position: usize) -> u32 {
+        match direction {
+            Direction::Forward => self.departure_at(trip, position),
+            Direction::Backward => self.arrival_at(trip, position),
+        }
+    }
+
+    /// Earliest trip of any day departing from the position at the time or later,
+    /// for backward search the latest trip arriving at the time or earlier
+    pub(super) fn find_trip(&self, direction: Direction, position: usize, time: u32) -> Option<DayTrip> {
+        (0..self.days.len())
+            .filter_map(|day| {
+                let timetable = &self.timetables[day];
+                let offset = self.days[day].offset;
+                let is_active = |service_idx: ServiceIdx| self.days[day].active_services[service_idx as usize];
+
+                // Trips of a day starting after the time all depart after it
+                let trip = match direction {
+                    Direction::Forward => timetable.earliest_trip(position, time.saturating_sub(offset), is_active),
+                    Direction::Backward => time.checked_sub(offset).and_then(|time| timetable.latest_trip(position, time, is_active)),
+                };
+                trip.map(|trip| DayTrip { day, trip })
+            })
+            .reduce(|best, trip| if self.is_better_trip(direction, position, trip, best) { trip } else { best })
+    }
+
+    /// Whether the trip runs on its day and can be boarded at the position (or for backward search left there) at the time
+    pub(super) fn can_board(&self, direction: Direction, trip: DayTrip, position: usize, time: u32) -> bool {
+        let day = &self.days[trip.day];
+        let is_active = day.active_services[self.pattern.services[trip.trip] as usize];
+
+        is_active && self.stops_at(trip, position) && !direction.is_better(self.boarding_time(direction, trip, position), Some(time))
+    }
+
+    /// Whether the trip is not cancelled and does not skip the stop at the position
+    pub(super) fn stops_at(&self, trip: DayTrip, position: usize) -> bool {
+        self.timetables[trip.day].stops_at(trip.trip, position)
+    }
+
+    /// Compared by the times rather than by the order, delayed trips can overtake each other
+    pub(super) fn is_better_trip(&self, direction: Direction, position: usize, trip: DayTrip, than: DayTrip) -> bool {
+        let key = |trip: DayTrip| (self.boarding_time(direction, trip, position), trip.day, trip.trip);
+        match direction {
+            Direction::Forward => key(trip) < key(than),
+            Direction::Backward => key(trip) > key(than),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(super) enum Segment {
+    /// (pattern, trip, boarding position, alighting position)
+    Transit(usize, DayTrip, usize, usize),
+    /// Index into the footpaths of the transit index
+    Walk(usize),
+}
+
+/// Time a trip can be boarded at (or for backward search left at) the stop reached through the segment
+/// at the time, `None` when changing vehicles there is not possible. Stops of round 0 were reached
+/// without any vehicle, so the first trip is boarded right away
+pub(super) fn ready_time(index: &TransitIndex, direction: Direction, stop: StopIdx, time: u32, segment: Option<Segment>, round: usize) -> Option<u32> {
+    let change_time = match segment {
+        _ if round == 0 => Some(0),
+        Some(Segment::Walk(footpath_idx)) if index.footpaths[footpath_idx].guaranteed => Some(0),
+        _ => index.change_times[stop as usize],
+    };
+
+    change_time.map(|change_time| direction.shift(time, change_time))
+}
+
+/// Pattern and trip the vehicle of the trip continues as after the last stop of its pattern, or for
+/// backward search the ones it came as before the first stop, from the blocks of trips.txt
+pub(super) fn block_continuation(index: &TransitIndex, direction: Direction, pattern_idx: usize, trip: DayTrip) -> Option<(usize, DayTrip)> {
+    let trip_idx = index.patterns[pattern_idx].trips[trip.trip] as usize;
+    let other_trip = match direction {
+        Direction::Forward => index.block_successors[trip_idx],
+        Direction::Backward => index.block_predecessors[trip_idx],
+    }?;
+
+    let (other_pattern, other_row) = index.trip_patterns[other_trip as usize]?;
+    Some((other_pattern, DayTrip { day: trip.day, trip: other_row }))
+}
+
+/// Pattern, trip and its first position (for backward search the last one) the vehicle of the trip ridden from
+/// the fixed position continues as at the end of the scan. Staying seated needs no change time and is not
+/// another trip, so the continuation is scanned within the same round
+pub(super) fn in_seat_continuation(
+    index: &TransitIndex,
+    delays: &DelayOverlay,
+    modes: ModeFilter,
+    direction: Direction,
+    timetables: &DayTimetables,
+    trip: DayTrip,
+    fixed_position: usize,
+) -> Option<(usize, DayTrip, usize)> {
+    let end_position = direction.last_position(timetables.pattern);
+    if fixed_position == end_position || !timetables.stops_at(trip, end_position) {
+        return None;
+    }
+
+    let (next_pattern, next_trip) = block_continuation(index, direction, timetables.pattern_idx, trip)?;
+    if !modes.allows(index.routes[index.patterns[next_pattern].route as usize].mode) {
+        return None;
+    }
+
+    let time = match direction {
+        Direction::Forward => timetables.arrival_at(trip, end_position),
+        Direction::Backward => timetables.departure_at(trip, end_position),
+    };
+    let next_timetables = DayTimetables::new(index, delays, timetables.days, next_pattern);
+    let next_position = direction.first_position(next_timetables.pattern);
+
+    next_timetables
+        .can_board(direction, next_trip, next_position, time)
+        .then_some((next_pattern, next_trip, next_position))
+}
+
+impl Segment {
+    /// Stop the segment was entered from, which is where forward search boards
+    /// (or starts walking) and where backward search alights (or stops walking)
+    pub(super) fn previous_stop(self, index: &TransitIndex, direction: Direction) -> StopIdx {
+        match (self, direction) {
+            (Segment::Transit(pattern_idx, _, boarding_position, _), Direction::Forward) => index.patterns[pattern_idx].stops[boarding_position],
+            (Segment::Transit(pattern_idx, _, _, alighting_position), Direction::Backward) => index.patterns[pattern_idx].stops[alighting_position],
+            (Segment::Walk(footpath_idx), Direction::Forward) => index.footpaths[footpath_idx].from_stop,
+            (Segment::Walk(footpath_idx), Direction::Backward) => index.footpaths[footpath_idx].to_stop,
+        }
+    }
+}
+
+/// (pattern, position the scan starts from, trip already boarded there with its fixed position)
+type PatternScan = (usize, usize, Option<(DayTrip, usize)>);
+
+#[derive(Clone, Copy)]
+struct Label {
+    /// Arrival at the stop for forward search, departure from the stop for backward search
+    time: u32,
+    /// How the stop was reached, `None` for the stops the search started from
+    segment: Option<Segment>,
+}
+
+struct Search<'a> {
+    index: &'a TransitIndex,
+    delays: &'a DelayOverlay,
+    modes: ModeFilter,
+    direction: Direction,
+    /// Walks from (or to) the requested places, keyed by the platform
+    sources: HashMap<StopIdx, Access>,
+    targets: HashMap<StopIdx, Access>,
+    /// Platforms where the search ends, with the walk still needed after reaching them
+    destinations: HashMap<StopIdx, u32>,
+    /// rounds[k] holds labels of stops improved in round k
+    rounds: Vec<HashMap<StopIdx, Label>>,
+    marked_stops: HashSet<StopIdx>,
+    /// (time at the requested place, platform) of the best journey of every round
+    best_destinations: Vec<Option<(u32, StopIdx)>>,
+}
+
+/// Finds the journey with the earliest arrival for depart-at queries, or the journey
+/// leaving as late as possible for arrive-by queries. From journeys with the same
+/// arrival (or departure), the one with fewer transfers wins
+pub fn find_journey<'a>(index: &'a TransitIndex, query: &RoutingQuery<'a>) -> Option<Vec<Leg<'a>>> {
+    let (direction, time) = match query.time {
+        TimeConstraint::DepartAt(time) | TimeConstraint::DepartBetween(time, _) => (Direction::Forward, time),
+        TimeConstraint::ArriveBy(time) => (Direction::Backward, time),
+    };
+
+    let mut search = Search::new(index, query, direction);
+    search.run(time, query.max_transfers, &query.days);
+    search.best_journey(query)
+}
+
+/// Finds every journey departing within the window of the query which is not dominated by
+/// another one leaving later, arriving earlier or with fewer transfers, the earliest departure first
+pub fn find_profile<'a>(index: &'a TransitIndex, query: &RoutingQuery<'a>) -> Vec<Vec<Leg<'a>>> {
+    let TimeConstraint::DepartBetween(window_start, window_end) = query.time else {
+        return find_journey(index, query).into_iter().collect();
+    };
+
+    let mut search = Search::new(index, query, Direction::Forward);
+    // (departure, round, legs)
+    let mut journeys = Vec::new();
+
+    // The last run from the start of the window finds the walk without any trip, which
+    // could leave at any time, so it is listed only once. Journeys slower than the walk are
+    // pruned by it in every run
+    let mut departure_times = search.departure_times(window_start, window_end, &query.days);
+    if departure_times.last() != Some(&window_start) {
+        departure_times.push(window_start);
+    }
+
+    // A run can reach the destination with a trip leaving after the window, like one of the next day,
+    // which is not listed, it only prunes the slower journeys of the earlier runs
+    let departs_in_window = |legs: &[Leg]| {
+        let departure = legs.first().map(Leg::get_departure_time);
+        departure.is_some_and(|departure| {
+            query.days.iter().any(|day| day.date == departure.date && day.offset + departure.seconds <= window_end)
+        })
+    };
+
+    for departure_time in departure_times {
+        let previous_destinations = search.best_destinations.clone();
+        search.run(departure_time, query.max_transfers, &query.days);
+
+        for (round, &destination) in search.best_destinations.iter().enumerate() {
+            if round == 0 && departure_time != window_start {
+                continue;
+            }
+
+            if let Some((_, stop)) = destination.filter(|_| previous_destinations.get(round) != Some(&destination)) {
+                let legs = search.reconstruct(round, stop, query);
+                if departs_in_window(&legs) {
+                    journeys.push((departure_time, round, legs));
+                }
+            }
+        }
+    }
+
+    journeys.sort_by_key(|&(departure_time, round, _)| (departure_time, round));
+    journeys.into_iter().map(|(_, _, legs)| legs).collect()
+}
+
+impl<'a> Search<'a> {
+    fn new(index: &'a TransitIndex, query: &RoutingQuery<'a>, direction: Direction) -> Self {
+        let destinations = match direction {
+            Direction::Forward => &query.targets,
+            Direction::Backward => &query.sources,
+        };
+
+        Search {
+            index,
+            delays: query.delays,
+            modes: query.modes,
+            direction,
+            sources: query.sources.iter().map(|access| (access.stop, *access)).collect(),
+            targets: query.targets.iter().map(|access| (access.stop, *access)).collect(),
+            destinations: destinations.iter().map(|access| (access.stop, access.duration)).collect(),
+            rounds: vec![HashMap::new()],
+            marked_stops: HashSet::new(),
+            best_destinations: vec![None],
+        }
+    }
+
+    /// Runs the rounds from the time at the origins. Labels of a previous run are kept
+    /// and only improved, which is only valid for runs with earlier departures
+    fn run(&mut self, time: u32, max_transfers: usize, days: &[ServiceDay; 3]) {
+        let origins: Vec<Access> = match self.direction {
+            Direction::Forward => self.sources.values().copied().collect(),
+            Direction::Backward => self.targets.values().copied().collect(),
+        };
+
+        self.marked_stops.clear();
+        origins.iter().for_each(|access| {
+            let time = self.direction.shift(time, access.duration);
+            if self.can_improve(0, access.stop, time) {
+                self.improve(0, access.stop, Label { time, segment: None });
+            }
+        });
+        self.relax_footpaths(0);
+
+        for round in 1..=max_transfers + 1 {
+            if self.rounds.len() == round {
+                self.rounds.push(HashMap::new());
+                self.best_destinations.push(None);
+            }
+            self.scan_patterns(round, days);
+            self.relax_footpaths(round);
+
+            if self.marked_stops.is_empty() {
+                break;
+            }
+        }
+    }
+
+    /// Times the first trip of a journey can be reached at from the origins, within the window, the latest first.
+    /// The first trip is boarded at a platform of the origin, or at a platform a footpath away from it
+    fn departure_times(&self, window_start: u32, window_end: u32, days: &[ServiceDay; 3]) -> Vec<u32> {
+        let index = self.index;
+        let boarding_stops = self.sources.values().flat_map(|access| {
+            let walks = index.outgoing_footpaths[access.stop as usize].iter().map(|&footpath_idx| {
+                let footpath = &index.footpaths[footpath_idx];
+                (footpath.to_stop, access.duration + footpath.duration)
+            });
+            std::iter::once((access.stop, access.duration)).chain(walks)
+        });
+
+        let mut times: Vec<u32> = boarding_stops
+            .flat_map(|(stop, walk_duration)| {
+                index.stop_patterns[stop as usize]
+                    .iter()
+                    .filter(|&&(pattern_idx, _)| self.modes.allows(index.routes[index.patterns[pattern_idx].route as usize].mode))
+                    .flat_map(move |&(pattern_idx, position)| days.iter().map(move |day| (pattern_idx, position, day)))
+                    .flat_map(move |(pattern_idx, position, day)| {
+                        let timetable = self.delays.timetable(index, pattern_idx, day.date);
+                        timetable
+                            .trips_departing_from(position, (window_start + walk_duration).saturating_sub(day.offset))
+                            .into_iter()
+                            .filter(move |&trip_idx| day.active_services[timetable.pattern.services[trip_idx] as usize])
+                            .map(move |trip_idx| day.offset + timetable.departure_at(trip_idx, position) - walk_duration)
+                            .take_while(move |&time| time <= window_end)
+                    })
+            })
+            .collect();
+
+        times.sort_unstable_by(|a, b| b.cmp(a));
+        times.dedup();
+        times
+    }
+
+    /// Returns the best label of the stop from the rounds before the given one, together with its round.
+    /// Within a single run the labels only improve from round to round, so it is the one of the latest round,
+    /// range queries keep the labels of later departures which can be worse than the ones of earlier rounds
+    fn best_label(&self, round: usize, stop: StopIdx) -> Option<(usize, &Label)> {
+        (0..round.min(self.rounds.len()))
+            .filter_map(|r| self.rounds[r].get(&stop).map(|label| (r, label)))
+            .reduce(|best, candidate| if self.direction.is_better(candidate.1.time, Some(best.1.time)) { candidate } else { best })
+    }
+
+    fn improve(&mut self, round: usize, stop: StopIdx, label: Label) {
+        self.rounds[round].insert(stop, label);
+        self.marked_stops.insert(stop);
+
+        // Journeys without any trip or footpath are not planned
+        if let (Some(&duration), Some(_)) = (self.destinations.get(&stop), label.segment) {
+            let time = self.direction.shift(label.time, duration);
+            if self.direction.is_better(time, self.best_destinations[round].map(|(time, _)| time)) {
+                self.best_destinations[round] = Some((time, stop));
+            }
+        }
+    }
+
+    /// The time has to be better than the one of any label of the stop, and than any journey,
+    /// using at most the same number of trips
+    fn can_improve(&self, round: usize, stop: StopIdx, time: u32) -> bool {
+        let best_time = self.best_label(round + 1, stop).map(|(_, label)| label.time);
+        let best_destination_time = self.best_destinations[..=round]
+            .iter()
+            .flatten()
+            .map(|&(time, _)| time)
+            .reduce(|best, time| if self.direction.is_better(time, Some(best)) { time } else { best });
+
+        self.direction.is_better(time, best_time) && self.direction.is_better(time, best_destination_time)
+    }
+
+    fn scan_patterns(&mut self, round: usize, days: &[ServiceDay; 3]) {
+        let index = self.index;
+        let direction = self.direction;
+        let modes = self.modes;
+
+        // Every pattern is scanned only once per round, starting from the first
+        // (or for backward search the last) position where any of its stops was improved
+        let mut queue: HashMap<usize, usize> = HashMap::new();
+        self.marked_stops.drain().for_each(|stop| {
+            index.stop_patterns[stop as usize].iter().for_each(|&(pattern_idx, position)| {
+                if !modes.allows(index.routes[index.patterns[pattern_idx].route as usize].mode) {
+                    return;
+                }
+
+                let entry = queue.entry(pattern_idx).or_insert(position);
+                *entry = direction.best(*entry, position);
+            });
+        });
+
+        // Patterns the vehicle continues as are scanned within the same round
+        let mut scans: Vec<PatternScan> = queue
+            .into_iter()
+            .map(|(pattern_idx, start_position)| (pattern_idx, start_position, None))
+            .collect();
+
+        while let Some((pattern_idx, start_position, boarded_trip)) = scans.pop() {
+            scans.extend(self.scan_pattern(round, days, pattern_idx, start_position, boarded_trip));
+        }
+    }
+
+    /// Scans the pattern from the position, riding the trip already boarded there if there is any.
+    /// Returns the scan of the pattern the vehicle of the trip reaching the end continues as
+    fn scan_pattern(
+        &mut self,
+        round: usize,
+        days: &[ServiceDay; 3],
+        pattern_idx: usize,
+        start_position: usize,
+        boarded_trip: Option<(DayTrip, usize)>,
+    ) -> Option<PatternScan> {
+        let index = self.index;
+        let direction = self.direction;
+        let timetables = DayTimetables::new(index, self.delays, days, pattern_idx);
+        let pattern = timetables.pattern;
+        // (trip, position where the trip was boarded, or for backward search alighted)
+        let mut current_trip = boarded_trip;
+
+        for position in scan_order(direction, pattern, start_position) {
+            let stop = pattern.stops[position];
+            let is_ridden = |&(trip, fixed_position): &(DayTrip, usize)| fixed_position != position && timetables.stops_at(trip, position);
+
+            if let Some((trip, fixed_position)) = current_trip.filter(is_ridden) {
+                let (time, segment) = match direction {
+                    Direction::Forward => (timetables.arrival_at(trip, position), Segment::Transit(pattern_idx, trip, fixed_position, position)),
+                    Direction::Backward => (timetables.departure_at(trip, position), Segment::Transit(pattern_idx, trip, position, fixed_position)),
+                };
+
+                if self.can_improve(round, stop, time) {
+                    self.improve(round, stop, Label { time, segment: Some(segment) });
+                }
+            }
+
+            // Try to catch an earlier (or for backward search a later) trip of the pattern at this stop
+            if let Some(trip) = self.find_trip(round, &timetables, position) {
+                if current_trip.is_none_or(|(current, _)| timetables.is_better_trip(direction, position, trip, current)) {
+                    current_trip = Some((trip, position));
+                }
+            }
+        }
+
+        let (trip, fixed_position) = current_trip?;
+        let (next_pattern, next_trip, next_position) = in_seat_continuation(index, self.delays, self.modes, direction, &timetables, trip, fixed_position)?;
+        Some((next_pattern, next_position, Some((next_trip, next_position))))
+    }
+
+    /// Best trip of the pattern which can be boarded at the position (or for backward search left there)
+    /// from any label of the rounds before the given one, keeping to the minimum change time
+    fn find_trip(&self, round: usize, timetables: &DayTimetables, position: usize) -> Option<DayTrip> {
+        let direction = self.direction;
+        let stop = timetables.pattern.stops[position];
+
+        (0..round.min(self.rounds.len()))
+            .filter_map(|r| self.rounds[r].get(&stop).and_then(|label| ready_time(self.index, direction, stop, label.time, label.segment, r)))
+            .reduce(|best, time| if direction.is_better(time, Some(best)) { time } else { best })
+            .and_then(|time| timetables.find_trip(direction, position, time))
+    }
+
+    /// Walks from every stop improved in the round to the stops connected by a footpath.
+    /// Walking labels are not relaxed again, so two footpaths are never chained
+    fn relax_footpaths(&mut self, round: usize) {
+        let index = self.index;
+        let improved: Vec<(StopIdx, u32)> = self.marked_stops
+            .iter()
+            .filter_map(|&stop| self.rounds[round].get(&stop).map(|label| (stop, label.time)))
+            .collect();
+
+        for (stop, time) in improved {
+            let footpaths = match self.direction {
+                Direction::Forward => &index.outgoing_footpaths[stop as usize],
+                Direction::Backward => &index.incoming_footpaths[stop as usize],
+            };
+
+            for &footpath_idx in footpaths {
+                let footpath = &index.footpaths[footpath_idx];
+                let other_stop = match self.direction {
+                    Direction::Forward => footpath.to_stop,
+                    Direction::Backward => footpath.from_stop,
+                };
+                let walked_time = self.direction.shift(time, footpath.duration);
+
+                if self.can_improve(round, other_stop, walked_time) {
+                    self.improve(round, other_stop, Label { time: walked_time, segment: Some(Segment::Walk(footpath_idx)) });
+                }
+            }
+        }
+    }
+
+    fn best_journey(&self, query: &RoutingQuery<'a>) -> Option<Vec<Leg<'a>>> {
+        // The earliest round reaching the best time uses the fewest trips
+        let (round, destination) = (0..self.rounds.len())
+            .flat_map(|round| self.destinations.iter().map(move |(&destination, &duration)| (round, destination, duration)))
+            .filter_map(|(round, destination, duration)| {
+                self.rounds[round]
+                    .get(&destination)
+                    .filter(|l| l.segment.is_some())
+                    .map(|l| (self.direction.shift(l.time, duration), round, destination))
+            })
+            .reduce(|best, candidate| {
+                if self.direction.is_better(candidate.0, Some(best.0)) || (candidate.0 == best.0 && candidate.1 < best.1) {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .map(|(_, round, destination)| (round, destination))?;
+
+        Some(self.reconstruct(round, destination, query))
+    }
+
+    /// Label of the rounds before the given one the trip of the segment was boarded from, the best
+    /// label of the stop might have been reached too late to change to the trip
+    fn boarding_label(&self, round: usize, stop: StopIdx, segment: Segment, days: &[ServiceDay; 3]) -> Option<(usize, &Label)> {
+        let Segment::Transit(pattern_idx, trip, boarding_position, alighting_position) = segment else {
+            return None;
+        };
+
+        let index = self.index;
+        let direction = self.direction;
+        let timetables = DayTimetables::new(index, self.delays, days, pattern_idx);
+        let position = match direction {
+            Direction::Forward => boarding_position,
+            Direction::Backward => alighting_position,
+        };
+
+        (0..round.min(self.rounds.len()))
+            .filter_map(|r| self.rounds[r].get(&stop).map(|label| (r, label)))
+            .filter(|&(r, label)| {
+                ready_time(index, direction, stop, label.time, label.segment, r)
+                    .is_some_and(|time| !direction.is_better(timetables.boarding_time(direction, trip, position), Some(time)))
+            })
+            .reduce(|best, candidate| if direction.is_better(candidate.1.time, Some(best.1.time)) { candidate } else { best })
+    }
+
+    /// Pushes the segments of the trips the vehicle of the segment came as (or for backward search continues as)
+    /// with the passenger seated, and returns the label of the rounds before the given one the first of them
+    /// was boarded from. Segments which do not lead to such a label are taken back
+    fn continued_from(&self, round: usize, segment: Segment, days: &[ServiceDay; 3], chain: &mut Vec<(u32, Segment)>) -> Option<(usize, &Label)> {
+        let Segment::Transit(pattern_idx, trip, boarding_position, alighting_position) = segment else {
+            return None;
+        };
+
+        let index = self.index;
+        let direction = self.direction;
+        let fixed_position = match direction {
+            Direction::Forward => boarding_position,
+            Direction::Backward => alighting_position,
+        };
+        if fixed_position != direction.first_position(&index.patterns[pattern_idx]) {
+            return None;
+        }
+
+        let (previous_pattern, previous_trip) = block_continuation(index, direction.reversed(), pattern_idx, trip)?;
+        let timetables = DayTimetables::new(index, self.delays, days, previous_pattern);
+        let pattern = timetables.pattern;
+        let end_position = direction.last_position(pattern);
+        let time = match direction {
+            Direction::Forward => timetables.arrival_at(previous_trip, end_position),
+            Direction::Backward => timetables.departure_at(previous_trip, end_position),
+        };
+
+        for position in scan_order(direction, pattern, direction.first_position(pattern)).filter(|&position| position != end_position) {
+            let previous_segment = match direction {
+                Direction::Forward => Segment::Transit(previous_pattern, previous_trip, position, end_position),
+                Direction::Backward => Segment::Transit(previous_pattern, previous_trip, end_position, position),
+            };
+            let stop = previous_segment.previous_stop(index, direction);
+
+            chain.push((time, previous_segment));
+            let label = self.boarding_label(round, stop, previous_segment, days)
+                .or_else(|| self.continued_from(round, previous_segment, days, chain));
+            if label.is_some() {
+                return label;
+            }
+            chain.pop();
+        }
+
+        None
+    }
+
+    fn reconstruct(&self, round: usize, stop: StopIdx, query: &RoutingQuery<'a>) -> Vec<Leg<'a>> {
+        let mut chain = Vec::new();
+        let mut label = self.rounds[round].get(&stop).map(|label| (round, label));
+
+        while let Some((round, &Label { time, segment: Some(segment) })) = label {
+            let previous_stop = segment.previous_stop(self.index, self.direction);
+
+            chain.push((time, segment));
+            label = match segment {
+                Segment::Transit(..) => self.boarding_label(round, previous_stop, segment, &query.days)
+                    .or_else(|| self.continued_from(round, segment, &query.days, &mut chain)),
+                // Footpaths are relaxed within the same round as the label they start from
+                Segment::Walk(_) => self.best_label(round + 1, previous_stop),
+            };
+        }
+
+        let timetable = |pattern_idx: usize, date| self.delays.timetable(self.index, pattern_idx, date);
+        build_legs(self.index, timetable, self.direction, &chain, stop, query)
+    }
+}
+
+/// Builds the legs of a journey from the chain of its segments, each with the time of its label,
+/// starting from the segment reaching the stop where the search ended. Trips keep the times of
+/// their own service day, walks get the times of the search
+pub(super) fn build_legs<'a>(
+    index: &'a TransitIndex,
+    timetable: impl Fn(usize, NaiveDate) -> Timetable<'a>,
+    direction: Direction,
+    chain: &[(u32, Segment)],
+    end_stop: StopIdx,
+    query: &RoutingQuery<'a>,
+) -> Vec<Leg<'a>> {
+    let search_date = query.days[0].date;
+    let mut legs = Vec::new();
+    // (departure, arrival) of every leg in the times of the search
+    let mut times = Vec::new();
+    let mut origin_stop = end_stop;
+
+    // Forward search walks back from the target, backward search walks forward from the source
+    for &(time, segment) in chain {
+        let leg = match segment {
+            Segment::Transit(pattern_idx, trip, boarding_position, alighting_position) => {
+                let day = &query.days[trip.day];
+                let timetable = timetable(pattern_idx, day.date);
+                times.push((
+                    day.offset + timetable.departure_at(trip.trip, boarding_position),
+                    day.offset + timetable.arrival_at(trip.trip, alighting_position),
+                ));
+                Leg::Transit(index.get_direct_trip(timetable, day.date, trip.trip, boarding_position, alighting_position))
+            },
+            Segment::Walk(footpath_idx) => {
+                let footpath = &index.footpaths[footpath_idx];
+                let (departure_time, arrival_time) = match direction {
+                    Direction::Forward => (time - footpath.duration, time),
+                    Direction::Backward => (time, time + footpath.duration),
+                };
+                times.push((departure_time, arrival_time));
+                Leg::Walk(Walk {
+                    from_stop: Some(footpath.from_stop),
+                    to_stop: Some(footpath.to_stop),
+                    service_date: search_date,
+                    departure_time,
+                    arrival_time,
+                    distance: footpath.distance,
+                })
+            },
+        };
+
+        legs.push(leg);
+        origin_stop = segment.previous_stop(index, direction);
+    }
+
+    let (source_stop, target_stop) = match direction {
+        Direction::Forward => {
+            legs.reverse();
+            times.reverse();
+            (origin_stop, end_stop)
+        },
+        Direction::Backward => (end_stop, origin_stop),
+    };
+
+    // Walk to the first platform right before the first leg and from the last platform right after the last leg
+    let access = query.sources.iter().find(|access| access.stop == source_stop);
+    let egress = query.targets.iter().find(|egress| egress.stop == target_stop);
+
+    if let (Some(access), Some(&(departure, _))) = (access, times.first()) {
+        if access.duration > 0 {
+            legs.insert(0, Leg::Walk(Walk {
+                from_stop: None,
+                to_stop: Some(access.stop),
+                service_date: search_date,
+                departure_time: departure.saturating_sub(access.duration),
+                arrival_time: departure,
+                distance: access.distance,
+            }));
+        }
+    }
+
+    if let (Some(egress), Some(&(_, arrival))) = (egress, times.last()) {
+        if egress.duration > 0 {
+            legs.push(Leg::Walk(Walk {
+                from_stop: Some(egress.stop),
+                to_stop: None,
+                service_date: search_date,
+                departure_time: arrival,
+                arrival_time: arrival + egress.duration,
+                distance: egress.distance,
+            }));
+        }
+    }
+
+    legs
+}
+
+pub(super) fn scan_order(direction: Direction, pattern: &TripPattern, start_position: usize) -> Box<dyn Iterator<Item = usize>> {
+    match direction {
+        Direction::Forward => Box::new(start_position..pattern.stops.len()),
+        Direction::Backward => Box::new((0..=start_position).rev()),
+    }
+}
@@ -0,0 +1,440 @@
+//! Checks shared by the routers, run on a small synthetic feed: a grid of stops with
+//! a bus line along every row, a tram line along every column and a diagonal
+//! trolleybus line, in both directions, and a night bus along the first row
+//! running past midnight as two trips of one block. Every stop has a platform for the buses and
+//! one for the trams, and footpaths connect only the platforms of the same stop,
+//! so walking once is as good as walking any number of times and both routers
+//! answer the same question
+
+use std::{collections::HashMap, fmt::Write, sync::OnceLock};
+use chrono::NaiveDate;
+use crate::{
+    calendar::ServiceTime,
+    model::{Mode, ModeFilter},
+    realtime::DelayOverlay,
+    test_feed,
+    transit_index::TransitIndex,
+};
+use super::{Algorithm, JourneyOptions, Leg, Place, SortBy, TimeConstraint, TransferOptions, WalkingOptions};
+
+const GRID_SIZE: usize = 5;
+const MIN_CHANGE_TIME: u32 = 60;
+
+fn date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()
+}
+
+fn index() -> &'static TransitIndex {
+    static INDEX: OnceLock<TransitIndex> = OnceLock::new();
+    INDEX.get_or_init(|| {
+        let gtfs = write_feed();
+        let transfers = TransferOptions {
+            min_change_time: MIN_CHANGE_TIME,
+            mode_change_times: Default::default(),
+            station_change_times: HashMap::new(),
+        };
+        TransitIndex::new(&gtfs, WalkingOptions { radius: 300.0, speed: 1.4 }, &transfers)
+    })
+}
+
+fn write_feed() -> gtfs_structures::Gtfs {
+    let mut stops = String::from("stop_id,stop_name,stop_lat,stop_lon\n");
+    for row in 0..GRID_SIZE {
+        for column in 0..GRID_SIZE {
+            // Neighbouring stops are about 700 m apart, the platforms of a stop 40 m
+            let (lat, lon) = (48.10 + row as f64 * 0.0063, 17.10 + column as f64 * 0.0095);
+            writeln!(stops, "S{}{}a,Stop {}-{},{},{}", row, column, row, column, lat, lon).unwrap();
+            writeln!(stops, "S{}{}b,Stop {}-{},{},{}", row, column, row, column, lat + 0.00036, lon).unwrap();
+        }
+    }
+
+    // (route_id, route_type, stops)
+    let mut lines: Vec<(String, u32, Vec<String>)> = Vec::new();
+    for i in 0..GRID_SIZE {
+        lines.push((format!("H{}", i), 3, (0..GRID_SIZE).map(|column| format!("S{}{}a", i, column)).collect()));
+        lines.push((format!("V{}", i), 0, (0..GRID_SIZE).map(|row| format!("S{}{}b", row, i)).collect()));
+    }
+    lines.push(("D".to_string(), 11, (0..GRID_SIZE).map(|i| format!("S{}{}a", i, i)).collect()));
+
+    let mut routes = String::from("route_id,route_short_name,route_type\n");
+    let mut trips = String::from("route_id,service_id,trip_id,block_id\n");
+    let mut stop_times = String::from("trip_id,arrival_time,departure_time,stop_id,stop_sequence\n");
+
+    for (line_no, (route_id, route_type, line_stops)) in lines.iter().enumerate() {
+        writeln!(routes, "{},{},{}", route_id, route_id, route_type).unwrap();
+
+        let headway = 300 + 60 * (line_no as u32 % 7);
+        let hop = if route_id == "D" { 240 } else { 120 + 30 * (line_no as u32 % 3) };
+
+        for (direction, stops) in [line_stops.clone(), line_stops.iter().rev().cloned().collect()].iter().enumerate() {
+            let mut start = 6 * 3600 + 37 * line_no as u32 + 90 * direction as u32;
+            let mut trip_no = 0;
+
+            while start < 9 * 3600 {
+                let trip_id = format!("{}_{}_{}", route_id, direction, trip_no);
+                writeln!(trips, "{},WD,{},", route_id, trip_id).unwrap();
+
+                stops.iter().enumerate().for_each(|(sequence, stop_id)| {
+                    let arrival = start + sequence as u32 * (hop + 30);
+                    let departure = if sequence == 0 { arrival } else { arrival + 30 };
+                    writeln!(stop_times, "{},{},{},{},{}", trip_id, time(arrival), time(departure), stop_id, sequence + 1).unwrap();
+                });
+
+                start += headway;
+                trip_no += 1;
+            }
+        }
+    }
+
+    // The night bus leaves at 24:20 of its service day and continues from the middle
+    // of the row as the next trip of its block right away, without the minimum change time
+    writeln!(routes, "N,N,3").unwrap();
+    writeln!(trips, "N,WD,N_0,NB").unwrap();
+    writeln!(trips, "N,WD,N_1,NB").unwrap();
+    (0..GRID_SIZE).for_each(|column| {
+        let departure = 24 * 3600 + 1200 + column as u32 * 150;
+        if column <= GRID_SIZE / 2 {
+            writeln!(stop_times, "N_0,{},{},S0{}a,{}", time(departure), time(departure), column, column + 1).unwrap();
+        }
+        if column >= GRID_SIZE / 2 {
+            writeln!(stop_times, "N_1,{},{},S0{}a,{}", time(departure), time(departure), column, column + 1).unwrap();
+        }
+    });
+
+    test_feed::load(&[
+        ("stops.txt", &stops),
+        ("routes.txt", &routes),
+        ("trips.txt", &trips),
+        ("stop_times.txt", &stop_times),
+    ])
+}
+
+fn time(seconds: u32) -> String {
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn options(algorithm: Algorithm, modes: ModeFilter) -> JourneyOptions {
+    JourneyOptions { max_transfers: 10, modes, num_itineraries: 1, sort: SortBy::Time, algorithm }
+}
+
+/// Arrival of the journey found by the router, `None` when there is none
+fn earliest_arrival(from: Place, to: Place, date: NaiveDate, time: u32, algorithm: Algorithm, modes: ModeFilter) -> Option<ServiceTime> {
+    let delays = DelayOverlay::default();
+
+    index()
+        .find_route(from, to, date, Some(TimeConstraint::DepartAt(time)), options(algorithm, modes), &delays)
+        .first()
+        .and_then(|legs| legs.last())
+        .map(Leg::get_arrival_time)
+}
+
+fn assert_engines_agree(from: Place, to: Place, time: u32, modes: ModeFilter) {
+    let raptor = earliest_arrival(from, to, date(), time, Algorithm::Raptor, modes);
+    let csa = earliest_arrival(from, to, date(), time, Algorithm::Csa, modes);
+    assert_eq!(raptor, csa, "earliest arrival differs when departing at {}", self::time(time));
+}
+
+#[test]
+fn engines_agree_on_earliest_arrival_between_stops() {
+    let index = index();
+    let all_modes = ModeFilter::new(None, None);
+
+    for from in &index.stations {
+        for to in index.stations.iter().filter(|to| to.stop_name != from.stop_name) {
+            for time in [6 * 3600, 6 * 3600 + 1397, 7 * 3600 + 2460, 8 * 3600 + 3300] {
+                assert_engines_agree(Place::Stop(from), Place::Stop(to), time, all_modes);
+            }
+        }
+    }
+}
+
+#[test]
+fn engines_agree_on_earliest_arrival_between_coordinates() {
+    // Near the stops 0-0, 1-2, 3-1 and 4-4
+    let places = [(17.1011, 48.1008), (17.1195, 48.1071), (17.1090, 48.1194), (17.1385, 48.1250)];
+    let all_modes = ModeFilter::new(None, None);
+
+    for &(from_lon, from_lat) in &places {
+        for &(to_lon, to_lat) in places.iter().filter(|&&place| place != (from_lon, from_lat)) {
+            let from = Place::Coordinates { longitude: from_lon, latitude: from_lat };
+            let to = Place::Coordinates { longitude: to_lon, latitude: to_lat };
+            assert_engines_agree(from, to, 6 * 3600 + 1200, all_modes);
+        }
+    }
+}
+
+#[test]
+fn engines_agree_on_earliest_arrival_with_excluded_modes() {
+    let index = index();
+    let without_trams = ModeFilter::new(None, Some(vec![Mode::Tram]));
+
+    for from in index.stations.iter().step_by(3) {
+        for to in index.stations.iter().filter(|to| to.stop_name != from.stop_name) {
+            assert_engines_agree(Place::Stop(from), Place::Stop(to), 7 * 3600, without_trams);
+        }
+    }
+}
+
+#[test]
+fn engines_go_on_with_the_trips_of_the_next_day() {
+    let index = index();
+    let (from, to) = (Place::Stop(&index.stations[0]), Place::Stop(&index.stations[index.stations.len() - 1]));
+    let all_modes = ModeFilter::new(None, None);
+    let next_date = date().succ_opt().unwrap();
+    let friday = NaiveDate::from_ymd_opt(2026, 10, 23).unwrap();
+
+    for algorithm in [Algorithm::Raptor, Algorithm::Csa] {
+        // After the last trip of a Monday the first trips of Tuesday are taken, nothing runs on Saturday
+        let arrival = earliest_arrival(from, to, date(), 22 * 3600, algorithm, all_modes);
+        assert!(arrival.is_some_and(|arrival| arrival.date == next_date), "no journey on the next day");
+        assert_eq!(earliest_arrival(from, to, friday, 22 * 3600, algorithm, all_modes), None);
+    }
+    assert_engines_agree(from, to, 22 * 3600, all_modes);
+}
+
+#[test]
+fn engines_find_night_trips_of_the_previous_day() {
+    let index = index();
+    let find_stop = |name: &str| index.stations.iter().find(|stop| stop.stop_name == name).unwrap();
+    let (from, to) = (Place::Stop(find_stop("Stop 0-0")), Place::Stop(find_stop("Stop 0-4")));
+    let all_modes = ModeFilter::new(None, None);
+    let arrival = Some(ServiceTime { date: date(), seconds: 24 * 3600 + 1200 + 4 * 150 });
+
+    for algorithm in [Algorithm::Raptor, Algorithm::Csa] {
+        // Before midnight on the service day of the trip, and after midnight on the next day
+        assert_eq!(earliest_arrival(from, to, date(), 23 * 3600 + 50 * 60, algorithm, all_modes), arrival);
+        assert_eq!(earliest_arrival(from, to, date().succ_opt().unwrap(), 10 * 60, algorithm, all_modes), arrival);
+    }
+}
+
+#[test]
+fn engines_stay_seated_through_block_continuations() {
+    let index = index();
+    let find_stop = |name: &str| index.stations.iter().find(|stop| stop.stop_name == name).unwrap();
+    let (from, to) = (Place::Stop(find_stop("Stop 0-0")), Place::Stop(find_stop("Stop 0-4")));
+    let delays = DelayOverlay::default();
+    let arrival = 24 * 3600 + 1200 + 4 * 150;
+
+    // Riding on from N_0 to N_1 takes a single trip, for both depart-at and arrive-by queries of RAPTOR and McRAPTOR
+    for time in [TimeConstraint::DepartAt(23 * 3600 + 50 * 60), TimeConstraint::ArriveBy(arrival)] {
+        for num_itineraries in [1, 3] {
+            let options = JourneyOptions { max_transfers: 0, num_itineraries, ..options(Algorithm::Raptor, ModeFilter::new(None, None)) };
+            let journeys = index.find_route(from, to, date(), Some(time), options, &delays);
+            let legs = journeys.first().expect("no journey on the night bus");
+
+            let trips: Vec<&str> = legs.iter().filter_map(|leg| match leg {
+                Leg::Transit(trip) => Some(trip.trip.id.as_str()),
+                Leg::Walk(_) => None,
+            }).collect();
+            assert_eq!(trips, ["N_0", "N_1"]);
+            assert_eq!(legs.last().map(|leg| leg.get_arrival_time().seconds), Some(arrival));
+            assert_eq!(index.count_transfers(legs), 0);
+        }
+    }
+}
+
+#[test]
+fn engines_keep_the_minimum_change_time() {
+    let index = index();
+    let all_modes = ModeFilter::new(None, None);
+    let delays = DelayOverlay::default();
+
+    for from in index.stations.iter().step_by(2) {
+        for to in index.stations.iter().filter(|to| to.stop_name != from.stop_name) {
+            for algorithm in [Algorithm::Raptor, Algorithm::Csa] {
+                let journeys = index.find_route(
+                    Place::Stop(from), Place::Stop(to), date(),
+                    Some(TimeConstraint::DepartAt(7 * 3600)), options(algorithm, all_modes), &delays,
+                );
+
+                // Every vehicle after the first one is boarded at least the minimum change time after leaving the previous one
+                for legs in &journeys {
+                    let mut left_vehicle = None;
+                    for leg in legs {
+                        if let (Leg::Transit(_), Some(left)) = (leg, left_vehicle) {
+                            assert!(leg.get_departure_time().seconds >= left + MIN_CHANGE_TIME, "change too short from {} to {}", from.stop_name, to.stop_name);
+                        }
+                        if let Leg::Transit(_) = leg {
+                            left_vehicle = Some(leg.get_arrival_time().seconds);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Earliest arrival from Origin to Destination departing at 08:00, changing at the Hub station whose two
+/// platforms 30 m apart are served by a line arriving at 08:10 and a line leaving at 08:10:40 and 08:20
+fn arrival_changing_at_station(transfers: &str) -> [Option<u32>; 2] {
+    let index = test_feed::index(&[
+        ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon,location_type,parent_station\n\
+            ST,Hub,48.1,17.1,1,\nA,Hub,48.1,17.1,0,ST\nB,Hub,48.10027,17.1,0,ST\n\
+            O,Origin,48.09,17.1,0,\nD,Destination,48.11,17.1,0,\n"),
+        ("routes.txt", "route_id,route_short_name,route_type\nL1,1,3\nL2,2,3\n"),
+        ("trips.txt", "route_id,service_id,trip_id\nL1,WD,L1_0\nL2,WD,L2_0\nL2,WD,L2_1\n"),
+        ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+            L1_0,08:00:00,08:00:00,O,1\nL1_0,08:10:00,08:10:00,A,2\n\
+            L2_0,08:10:40,08:10:40,B,1\nL2_0,08:20:00,08:20:00,D,2\n\
+            L2_1,08:20:00,08:20:00,B,1\nL2_1,08:30:00,08:30:00,D,2\n"),
+        ("transfers.txt", &format!("from_stop_id,to_stop_id,transfer_type,min_transfer_time\n{}", transfers)),
+    ]);
+
+    let find_stop = |name: &str| Place::Stop(index.stations.iter().find(|stop| stop.stop_name == name).unwrap());
+    let delays = DelayOverlay::default();
+    [Algorithm::Raptor, Algorithm::Csa].map(|algorithm| {
+        index
+            .find_route(find_stop("Origin"), find_stop("Destination"), date(), Some(TimeConstraint::DepartAt(8 * 3600)), options(algorithm, ModeFilter::new(None, None)), &delays)
+            .first()
+            .and_then(|legs| legs.last())
+            .map(|leg| leg.get_arrival_time().seconds)
+    })
+}
+
+#[test]
+fn engines_apply_station_transfers_to_its_platforms() {
+    let (early, late) = (Some(8 * 3600 + 1200), Some(8 * 3600 + 1800));
+
+    // The walk and the change time miss the first trip
+    assert_eq!(arrival_changing_at_station(""), [late; 2]);
+    // A timed transfer of the station holds the first trip
+    assert_eq!(arrival_changing_at_station("ST,ST,1,\n"), [early; 2]);
+    // A rule between the platforms wins over the one of their station
+    assert_eq!(arrival_changing_at_station("ST,ST,1,\nA,B,2,300\n"), [late; 2]);
+    // No change is possible within the station
+    assert_eq!(arrival_changing_at_station("ST,ST,3,\n"), [None; 2]);
+}
+
+#[test]
+fn profile_lists_the_journeys_in_the_order_of_their_departures() {
+    // A direct trip leaving at 08:00 arrives at 08:40, a change at Transfer after leaving at 08:10 arrives at 08:30
+    let index = test_feed::index(&[
+        ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\nO,Origin,48.09,17.1\nX,Transfer,48.1,17.12\nD,Destination,48.11,17.1\n"),
+        ("routes.txt", "route_id,route_short_name,route_type\nL1,1,3\nL2,2,3\nL3,3,3\n"),
+        ("trips.txt", "route_id,service_id,trip_id\nL1,WD,L1_0\nL2,WD,L2_0\nL3,WD,L3_0\n"),
+        ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+            L1_0,08:00:00,08:00:00,O,1\nL1_0,08:40:00,08:40:00,D,2\n\
+            L2_0,08:10:00,08:10:00,O,1\nL2_0,08:15:00,08:15:00,X,2\n\
+            L3_0,08:17:00,08:17:00,X,1\nL3_0,08:30:00,08:30:00,D,2\n"),
+    ]);
+
+    let find_stop = |name: &str| Place::Stop(index.stations.iter().find(|stop| stop.stop_name == name).unwrap());
+    let delays = DelayOverlay::default();
+    let options = JourneyOptions { num_itineraries: usize::MAX, ..options(Algorithm::Raptor, ModeFilter::new(None, None)) };
+    let window = TimeConstraint::DepartBetween(7 * 3600 + 50 * 60, 8 * 3600 + 20 * 60);
+    let journeys = index.find_route(find_stop("Origin"), find_stop("Destination"), date(), Some(window), options, &delays);
+
+    // The later departure arrives earlier, the earlier one without a change is kept and listed first
+    let times: Vec<(u32, u32)> = journeys
+        .iter()
+        .map(|legs| (legs[0].get_departure_time().seconds, legs[legs.len() - 1].get_arrival_time().seconds))
+        .collect();
+    assert_eq!(times, [(8 * 3600, 8 * 3600 + 2400), (8 * 3600 + 600, 8 * 3600 + 1800)]);
+    assert_eq!(journeys.iter().map(|legs| index.count_transfers(legs)).collect::<Vec<_>>(), [0, 1]);
+}
+
+/// Trips of the journeys McRAPTOR finds from Origin to Destination departing at 08:00 on a feed where:
+/// L1 goes directly and arrives at 08:50, L7 goes directly too but arrives only at 08:55,
+/// L2 and L3 change at the two platforms of Hub and arrive at 08:30,
+/// and L6 leaves from Side Street, a walk from Origin, and arrives at 08:40
+fn pareto_trips(sort: SortBy, num_itineraries: usize) -> Vec<Vec<String>> {
+    let index = test_feed::index(&[
+        ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\n\
+            O,Origin,48.09,17.1\nO2,Side Street,48.0907,17.1\nXa,Hub,48.1,17.12\nXb,Hub,48.10036,17.12\nD,Destination,48.11,17.1\n"),
+        ("routes.txt", "route_id,route_short_name,route_type\nL1,1,3\nL2,2,3\nL3,3,3\nL6,6,3\nL7,7,3\n"),
+        ("trips.txt", "route_id,service_id,trip_id\nL1,WD,L1_0\nL2,WD,L2_0\nL3,WD,L3_0\nL6,WD,L6_0\nL7,WD,L7_0\n"),
+        ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+            L1_0,08:00:00,08:00:00,O,1\nL1_0,08:50:00,08:50:00,D,2\n\
+            L7_0,08:01:00,08:01:00,O,1\nL7_0,08:55:00,08:55:00,D,2\n\
+            L2_0,08:00:00,08:00:00,O,1\nL2_0,08:10:00,08:10:00,Xa,2\n\
+            L3_0,08:13:00,08:13:00,Xb,1\nL3_0,08:30:00,08:30:00,D,2\n\
+            L6_0,08:05:00,08:05:00,O2,1\nL6_0,08:40:00,08:40:00,D,2\n"),
+    ]);
+
+    let find_stop = |name: &str| Place::Stop(index.stations.iter().find(|stop| stop.stop_name == name).unwrap());
+    let delays = DelayOverlay::default();
+    let options = JourneyOptions { num_itineraries, sort, ..options(Algorithm::Raptor, ModeFilter::new(None, None)) };
+    index
+        .find_route(find_stop("Origin"), find_stop("Destination"), date(), Some(TimeConstraint::DepartAt(8 * 3600)), options, &delays)
+        .iter()
+        .map(|legs| legs.iter().filter_map(|leg| match leg {
+            Leg::Transit(trip) => Some(trip.trip.id.clone()),
+            Leg::Walk(_) => None,
+        }).collect())
+        .collect()
+}
+
+#[test]
+fn mc_raptor_keeps_the_journeys_not_worse_in_every_criterion() {
+    // The change is fastest, the walk to Side Street saves it, the direct trip from Origin saves the walk too,
+    // and the other direct trip arriving later is pruned
+    assert_eq!(pareto_trips(SortBy::Time, 5), [vec!["L2_0", "L3_0"], vec!["L6_0"], vec!["L1_0"]]);
+    assert_eq!(pareto_trips(SortBy::Transfers, 5), [vec!["L6_0"], vec!["L1_0"], vec!["L2_0", "L3_0"]]);
+    assert_eq!(pareto_trips(SortBy::Walking, 5), [vec!["L1_0"], vec!["L2_0", "L3_0"], vec!["L6_0"]]);
+    assert_eq!(pareto_trips(SortBy::Transfers, 2), [vec!["L6_0"], vec!["L1_0"]]);
+}
+
+#[test]
+fn arrive_by_leaves_as_late_as_possible() {
+    // Both trips arrive before 08:40, only the first one before 08:25
+    let index = test_feed::index(&[
+        ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\nO,Origin,48.09,17.1\nD,Destination,48.11,17.1\n"),
+        ("routes.txt", "route_id,route_short_name,route_type\nL1,1,3\n"),
+        ("trips.txt", "route_id,service_id,trip_id\nL1,WD,L1_0\nL1,WD,L1_1\n"),
+        ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+            L1_0,08:00:00,08:00:00,O,1\nL1_0,08:20:00,08:20:00,D,2\n\
+            L1_1,08:10:00,08:10:00,O,1\nL1_1,08:30:00,08:30:00,D,2\n"),
+    ]);
+
+    let find_stop = |name: &str| Place::Stop(index.stations.iter().find(|stop| stop.stop_name == name).unwrap());
+    let delays = DelayOverlay::default();
+    let first_trip = |arrive_by: u32, num_itineraries: usize| {
+        let options = JourneyOptions { num_itineraries, ..options(Algorithm::Raptor, ModeFilter::new(None, None)) };
+        index
+            .find_route(find_stop("Origin"), find_stop("Destination"), date(), Some(TimeConstraint::ArriveBy(arrive_by)), options, &delays)
+            .first()
+            .and_then(|legs| legs.iter().find_map(|leg| match leg {
+                Leg::Transit(trip) => Some(trip.trip.id.clone()),
+                Leg::Walk(_) => None,
+            }))
+    };
+
+    // RAPTOR and McRAPTOR
+    for num_itineraries in [1, 3] {
+        assert_eq!(first_trip(8 * 3600 + 2400, num_itineraries).as_deref(), Some("L1_1"));
+        assert_eq!(first_trip(8 * 3600 + 1500, num_itineraries).as_deref(), Some("L1_0"));
+        assert_eq!(first_trip(8 * 3600 + 1000, num_itineraries), None);
+    }
+}
+
+#[test]
+fn engines_stay_seated_when_another_trip_reaches_the_platform_first() {
+    // B1 continues as B2 at Hub, F reaches Hub half a minute earlier, too late to change to B2
+    let index = test_feed::index(&[
+        ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\nO,Origin,48.09,17.1\nX,Hub,48.1,17.1\nD,Destination,48.11,17.1\n"),
+        ("routes.txt", "route_id,route_short_name,route_type\nB,1,3\nF,2,3\n"),
+        ("trips.txt", "route_id,service_id,trip_id,block_id\nB,WD,B1,BK\nB,WD,B2,BK\nF,WD,F1,\n"),
+        ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+            B1,08:00:00,08:00:00,O,1\nB1,08:20:00,08:20:00,X,2\n\
+            F1,08:05:00,08:05:00,O,1\nF1,08:19:30,08:19:30,X,2\n\
+            B2,08:20:00,08:20:00,X,1\nB2,08:40:00,08:40:00,D,2\n"),
+    ]);
+
+    let find_stop = |name: &str| Place::Stop(index.stations.iter().find(|stop| stop.stop_name == name).unwrap());
+    let delays = DelayOverlay::default();
+    for algorithm in [Algorithm::Raptor, Algorithm::Csa] {
+        let journeys = index.find_route(
+            find_stop("Origin"), find_stop("Destination"), date(),
+            Some(TimeConstraint::DepartAt(8 * 3600)), options(algorithm, ModeFilter::new(None, None)), &delays,
+        );
+        let legs = journeys.first().expect("no journey on the block");
+
+        let trips: Vec<&str> = legs.iter().filter_map(|leg| match leg {
+            Leg::Transit(trip) => Some(trip.trip.id.as_str()),
+            Leg::Walk(_) => None,
+        }).collect();
+        assert_eq!(trips, ["B1", "B2"]);
+        assert_eq!(legs.last().map(|leg| leg.get_arrival_time().seconds), Some(8 * 3600 + 2400));
+        assert_eq!(index.count_transfers(legs), 0);
+    }
+}
@@ -0,0 +1,310 @@
+//! Stop name search which does not mind missing diacritics, letter case, punctuation or
+//! unfinished words. Names and queries are normalised the same way, the score of a name
+//! then combines how well it matches the query as a prefix, word by word and by trigrams.
+//! Only the names sharing a word prefix or a trigram with the query are scored, found
+//! through indices built together with the search
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+/// Score below which a name is not considered a match for the query
+const MIN_SCORE: f32 = 0.2;
+
+#[derive(Serialize, Deserialize)]
+struct SearchName {
+    /// Normalised name
+    text: String,
+    /// For every character of the normalised name, position of the character of the original name it comes from
+    original_chars: Vec<u32>,
+    trigram_count: u32,
+}
+
+/// Normalised stop names, in the same order as the names the search was built from
+#[derive(Serialize, Deserialize)]
+pub struct StopSearch {
+    names: Vec<SearchName>,
+    /// (word, position of the name) of every word of every name, sorted by the word
+    words: Vec<(String, u32)>,
+    /// Trigram -> positions of the names containing it
+    trigrams: HashMap<String, Vec<u32>>,
+}
+
+/// A name matching the query
+pub struct SearchMatch {
+    /// Position of the name
+    pub position: usize,
+    /// From 0 to 1 for an exact match
+    pub score: f32,
+    /// (start, end) character positions of the matched part of the original name,
+    /// `None` when the name matched only by its trigrams
+    pub highlight: Option<(usize, usize)>,
+}
+
+impl StopSearch {
+    pub fn new<'a>(names: impl Iterator<Item = &'a str>) -> Self {
+        let mut words = Vec::new();
+        let mut trigrams: HashMap<String, Vec<u32>> = HashMap::new();
+
+        let names = names
+            .enumerate()
+            .map(|(position, name)| {
+                let (text, original_chars) = normalize_with_positions(name);
+                let name_trigrams = trigrams_of(&text);
+
+                words.extend(text.split(' ').map(|word| (word.to_string(), position as u32)));
+                name_trigrams.iter().for_each(|trigram| trigrams.entry(trigram.clone()).or_default().push(position as u32));
+
+                SearchName { text, original_chars, trigram_count: name_trigrams.len() as u32 }
+            })
+            .collect();
+
+        words.sort();
+        StopSearch { names, words, trigrams }
+    }
+
+    /// Returns the names matching the query, the best match first.
+    /// Between names with the same score the shorter one wins
+    pub fn search(&self, query: &str) -> Vec<SearchMatch> {
+        let query = normalize(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        // Trigrams shared with the query, for every name
+        let query_trigrams = trigrams_of(&query);
+        let mut shared_trigrams = vec![0u32; self.names.len()];
+        let mut candidates: Vec<u32> = Vec::new();
+
+        query_trigrams.iter().filter_map(|trigram| self.trigrams.get(trigram)).flatten().for_each(|&position| {
+            if shared_trigrams[position as usize] == 0 {
+                candidates.push(position);
+            }
+            shared_trigrams[position as usize] += 1;
+        });
+
+        // Names sharing too few trigrams can still match by a word prefix
+        let mut prefixed = vec![false; self.names.len()];
+        query.split(' ').for_each(|query_word| {
+            let start = self.words.partition_point(|(word, _)| word.as_str() < query_word);
+            self.words[start..]
+                .iter()
+                .take_while(|(word, _)| word.starts_with(query_word))
+                .for_each(|&(_, position)| {
+                    if !prefixed[position as usize] && shared_trigrams[position as usize] == 0 {
+                        candidates.push(position);
+                    }
+                    prefixed[position as usize] = true;
+                });
+        });
+
+        let mut matches: Vec<SearchMatch> = candidates
+            .into_iter()
+            .filter_map(|position| {
+                let name = &self.names[position as usize];
+                let shared = shared_trigrams[position as usize];
+                let similarity = shared as f32 / (query_trigrams.len() as u32 + name.trigram_count - shared) as f32;
+
+                if similarity < MIN_SCORE && !prefixed[position as usize] {
+                    return None;
+                }
+
+                let (score, span) = score(&name.text, &query, similarity);
+                Some(SearchMatch {
+                    position: position as usize,
+                    score,
+                    highlight: span.map(|(start, end)| {
+                        (name.original_chars[start] as usize, name.original_chars[end - 1] as usize + 1)
+                    }),
+                })
+            })
+            .filter(|search_match| search_match.score >= MIN_SCORE)
+            .collect();
+
+        matches.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(self.names[a.position].text.len().cmp(&self.names[b.position].text.len()))
+                .then(a.position.cmp(&b.position))
+        });
+        matches
+    }
+}
+
+/// Lowercases the text, folds the diacritics and replaces punctuation with single spaces
+pub fn normalize(text: &str) -> String {
+    normalize_with_positions(text).0
+}
+
+/// Normalises the text, remembering for every character of the result
+/// the position of the character of the text it comes from
+fn normalize_with_positions(text: &str) -> (String, Vec<u32>) {
+    let mut normalized = String::with_capacity(text.len());
+    let mut positions = Vec::with_capacity(text.len());
+
+    text.chars()
+        .enumerate()
+        .flat_map(|(position, c)| c.to_lowercase().map(move |c| (position as u32, fold_diacritic(c))))
+        .for_each(|(position, c)| {
+            if c.is_alphanumeric() {
+                normalized.push(c);
+                positions.push(position);
+            } else if !normalized.is_empty() && !normalized.ends_with(' ') {
+                normalized.push(' ');
+                positions.push(position);
+            }
+        });
+
+    if normalized.ends_with(' ') {
+        normalized.pop();
+        positions.pop();
+    }
+
+    (normalized, positions)
+}
+
+/// Covers the Slovak alphabet and the letters of the neighbouring languages
+fn fold_diacritic(c: char) -> char {
+    match c {
+        'á' | 'ä' | 'à' | 'â' | 'ą' => 'a',
+        'č' | 'ć' | 'ç' => 'c',
+        'ď' => 'd',
+        'é' | 'ě' | 'ë' | 'è' | 'ę' => 'e',
+        'í' | 'ì' | 'î' => 'i',
+        'ĺ' | 'ľ' | 'ł' => 'l',
+        'ň' | 'ń' => 'n',
+        'ó' | 'ô' | 'ö' | 'ő' | 'ò' => 'o',
+        'ŕ' | 'ř' => 'r',
+        'š' | 'ś' => 's',
+        'ť' => 't',
+        'ú' | 'ů' | 'ü' | 'ű' | 'ù' => 'u',
+        'ý' => 'y',
+        'ž' | 'ź' | 'ż' => 'z',
+        c => c,
+    }
+}
+
+/// Distinct trigrams of the words of a normalised text, every word padded
+/// by two spaces in front and one behind, the same way as pg_trgm does
+fn trigrams_of(text: &str) -> Vec<String> {
+    let mut trigrams: Vec<String> = text
+        .split(' ')
+        .flat_map(|word| {
+            let padded: Vec<char> = "  ".chars().chain(word.chars()).chain(" ".chars()).collect();
+            padded.windows(3).map(|window| window.iter().collect::<String>()).collect::<Vec<_>>()
+        })
+        .collect();
+
+    trigrams.sort();
+    trigrams.dedup();
+    trigrams
+}
+
+/// Score of a normalised name for a normalised query, 1.0 only for an exact match, together
+/// with the (start, end) character positions of the part of the name matched by a prefix
+fn score(name: &str, query: &str, trigram_similarity: f32) -> (f32, Option<(usize, usize)>) {
+    let query_length = query.chars().count();
+
+    if name == query {
+        return (1.0, Some((0, query_length)));
+    }
+
+    // A longer part of the name typed scores higher, but never as high as the whole name
+    let prefix = if name.starts_with(query) {
+        0.7 + 0.25 * query.len() as f32 / name.len() as f32
+    } else {
+        0.0
+    };
+    let (words, words_span) = word_score(name, query);
+
+    let score = prefix.max(0.9 * words).max(trigram_similarity);
+    let span = if prefix > 0.0 { Some((0, query_length)) } else { words_span };
+    (score, span)
+}
+
+/// Average over the words of the query of how well they match a word of the name, either as
+/// the whole word or as its beginning, together with the span from the first matched character
+/// to the last one
+fn word_score(name: &str, query: &str) -> (f32, Option<(usize, usize)>) {
+    // (start character, word) of every word of the name
+    let mut start = 0;
+    let name_words: Vec<(usize, &str)> = name
+        .split(' ')
+        .map(|word| {
+            let word_start = start;
+            start += word.chars().count() + 1;
+            (word_start, word)
+        })
+        .collect();
+
+    let query_words: Vec<&str> = query.split(' ').collect();
+    let mut span: Option<(usize, usize)> = None;
+
+    let total: f32 = query_words
+        .iter()
+        .map(|query_word| {
+            let best = name_words
+                .iter()
+                .filter_map(|&(word_start, name_word)| {
+                    let score = match name_word.strip_prefix(query_word)? {
+                        "" => 1.0,
+                        _ => 0.6 + 0.4 * query_word.len() as f32 / name_word.len() as f32,
+                    };
+                    Some((score, word_start))
+                })
+                .max_by(|a, b| a.0.total_cmp(&b.0));
+
+            let Some((score, word_start)) = best else {
+                return 0.0;
+            };
+
+            let word_end = word_start + query_word.chars().count();
+            span = Some(span.map_or((word_start, word_end), |(start, end)| (start.min(word_start), end.max(word_end))));
+            score
+        })
+        .sum();
+
+    (total / query_words.len() as f32, span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, StopSearch};
+
+    const NAMES: [&str; 6] = ["Hlavná stanica", "Hlavné námestie", "Cintorín Slávičie údolie", "Hlavná", "Štúrova", "Vlastenecké nám."];
+
+    /// Names of the matches, the best one first
+    fn search(query: &str) -> Vec<&'static str> {
+        StopSearch::new(NAMES.into_iter()).search(query).iter().map(|search_match| NAMES[search_match.position]).collect()
+    }
+
+    #[test]
+    fn folds_diacritics_case_and_punctuation() {
+        assert_eq!(normalize("Cintorín Slávičie-údolie"), "cintorin slavicie udolie");
+        assert_eq!(normalize("  Vlastenecké nám. "), "vlastenecke nam");
+
+        assert_eq!(search("Hlavna stanica").first(), Some(&"Hlavná stanica"));
+        assert_eq!(search("sturova").first(), Some(&"Štúrova"));
+        assert_eq!(search("HLAVNÁ-STANICA").first(), Some(&"Hlavná stanica"));
+    }
+
+    #[test]
+    fn ranks_exact_names_before_prefixes_and_words() {
+        // The exact name first, then the name it is the beginning of, then the names sharing only some letters
+        let matches = search("hlavna");
+        assert_eq!(matches[..2], ["Hlavná", "Hlavná stanica"]);
+        assert!(matches.contains(&"Hlavné námestie"));
+
+        // Unfinished words of the query match the beginnings of the words of the name
+        assert_eq!(search("slav ud").first(), Some(&"Cintorín Slávičie údolie"));
+        assert!(search("xyz").is_empty());
+    }
+
+    #[test]
+    fn highlights_the_matched_part_of_the_original_name() {
+        let stop_search = StopSearch::new(NAMES.into_iter());
+        let highlight = |query: &str| stop_search.search(query).first().and_then(|search_match| search_match.highlight);
+
+        assert_eq!(highlight("hlavna stanica"), Some((0, 14)));
+        assert_eq!(highlight("slavicie ud"), Some((9, 20)));
+    }
+}
@@ -0,0 +1,167 @@
+//! Binary snapshot of a built `TransitIndex`. The file starts with a header made of
+//! the magic bytes, the format version, the checksum of the feed the index was
+//! built from and the CRC32 of the rest of the file, followed by the bincode encoded index
+
+use std::{fmt, fs, io, path::Path};
+use crate::transit_index::TransitIndex;
+
+const MAGIC: &[u8; 8] = b"BIMHDIDX";
+/// Has to be bumped whenever the layout of the index, or how it is built from the feed, changes
+const FORMAT_VERSION: u32 = 10;
+const HEADER_LENGTH: usize = MAGIC.len() + 12;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// File does not start with the magic bytes
+    NotASnapshot,
+    /// Snapshot was written by a different version of the server
+    UnsupportedVersion(u32),
+    /// Snapshot was built from a different feed
+    FeedChanged,
+    /// Encoded index does not match its CRC32
+    Corrupted,
+    Encoding(bincode::Error),
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(e: bincode::Error) -> Self {
+        SnapshotError::Encoding(e)
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::NotASnapshot => write!(f, "Not an index snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "Unsupported snapshot version {}", version),
+            SnapshotError::FeedChanged => write!(f, "Snapshot was built from a different feed"),
+            SnapshotError::Corrupted => write!(f, "Snapshot is corrupted"),
+            SnapshotError::Encoding(e) => write!(f, "Invalid snapshot: {}", e),
+        }
+    }
+}
+
+/// Writes the snapshot next to the target first and then renames it,
+/// so a running server never reads a half written file
+pub fn write(path: &Path, index: &TransitIndex, feed_checksum: u32) -> Result<(), SnapshotError> {
+    let body = bincode::serialize(index)?;
+    let mut bytes = Vec::with_capacity(HEADER_LENGTH + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&feed_checksum.to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+    bytes.extend_from_slice(&body);
+
+    let temporary_path = path.with_extension("tmp");
+    fs::write(&temporary_path, bytes)?;
+    fs::rename(&temporary_path, path)?;
+    Ok(())
+}
+
+/// Reads the snapshot, as long as it was built from a feed with the same checksum and is not corrupted
+pub fn read(path: &Path, feed_checksum: u32) -> Result<TransitIndex, SnapshotError> {
+    let bytes = fs::read(path)?;
+
+    if bytes.len() < HEADER_LENGTH || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(SnapshotError::NotASnapshot);
+    }
+
+    let read_u32 = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+    let version = read_u32(MAGIC.len());
+    if version != FORMAT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    if read_u32(MAGIC.len() + 4) != feed_checksum {
+        return Err(SnapshotError::FeedChanged);
+    }
+
+    let body = &bytes[HEADER_LENGTH..];
+    if read_u32(MAGIC.len() + 8) != crc32fast::hash(body) {
+        return Err(SnapshotError::Corrupted);
+    }
+
+    Ok(bincode::deserialize(body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+    use crate::{test_feed, transit_index::TransitIndex};
+    use super::{read, write, SnapshotError, FORMAT_VERSION, MAGIC};
+
+    const FEED_CHECKSUM: u32 = 0x5eed;
+
+    fn index() -> TransitIndex {
+        test_feed::index(&[
+            ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\nA,Alpha,48.1,17.1\nB,Beta,48.11,17.1\n"),
+            ("routes.txt", "route_id,route_short_name,route_type\nR,1,3\n"),
+            ("trips.txt", "route_id,service_id,trip_id\nR,WD,T\n"),
+            ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\nT,08:00:00,08:00:00,A,1\nT,08:05:00,08:05:00,B,2\n"),
+        ])
+    }
+
+    /// Writes the snapshot of the index to a new temporary file
+    fn written_snapshot(name: &str, index: &TransitIndex) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bimhd-test-{}-{}.idx", std::process::id(), name));
+        write(&path, index, FEED_CHECKSUM).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_the_written_index() {
+        let index = index();
+        let path = written_snapshot("round-trip", &index);
+
+        // Maps of the index are encoded in no particular order, so the contents are compared instead of the bytes
+        let loaded = read(&path, FEED_CHECKSUM).unwrap();
+        let stop_names = |index: &TransitIndex| index.stations.iter().map(|stop| stop.stop_name.clone()).collect::<Vec<_>>();
+        assert_eq!(stop_names(&loaded), stop_names(&index));
+        assert_eq!(loaded.patterns.len(), index.patterns.len());
+        assert_eq!(loaded.find_stop("beta").map(|stop| stop.stop_name.as_str()), Some("Beta"));
+        assert!(matches!(read(&path, FEED_CHECKSUM + 1), Err(SnapshotError::FeedChanged)));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_versions_and_files() {
+        let path = written_snapshot("version", &index());
+        let mut bytes = fs::read(&path).unwrap();
+
+        bytes[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(FORMAT_VERSION - 1).to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(read(&path, FEED_CHECKSUM), Err(SnapshotError::UnsupportedVersion(version)) if version == FORMAT_VERSION - 1));
+
+        fs::write(&path, b"stop_id,stop_name\n").unwrap();
+        assert!(matches!(read(&path, FEED_CHECKSUM), Err(SnapshotError::NotASnapshot)));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_corrupted_indices() {
+        let path = written_snapshot("corrupted", &index());
+        let mut bytes = fs::read(&path).unwrap();
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(read(&path, FEED_CHECKSUM), Err(SnapshotError::Corrupted)));
+
+        // A truncated index does not match its CRC32 either
+        fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(matches!(read(&path, FEED_CHECKSUM), Err(SnapshotError::Corrupted)));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use rstar::{primitives::GeomWithData, PointDistance, RTree};
+use serde::{Deserialize, Serialize};
+use crate::model::{Platform, StopIdx};
+
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+type PlatformPoint = GeomWithData<[f64; 2], StopIdx>;
+
+/// R-tree over platform coordinates. Coordinates are projected onto a plane
+/// tangent to the feed area (equirectangular projection), so the euclidean
+/// distance between two points is their distance in meters, which is precise
+/// enough on the scale of a single city
+#[derive(Serialize, Deserialize)]
+pub struct SpatialIndex {
+    tree: RTree<PlatformPoint>,
+    reference_latitude: f64,
+}
+
+impl SpatialIndex {
+    pub fn new(platforms: &[Platform]) -> Self {
+        let coordinates: Vec<(StopIdx, f64, f64)> = platforms
+            .iter()
+            .enumerate()
+            .filter_map(|(stop_idx, p)| Some((stop_idx as StopIdx, p.longitude?, p.latitude?)))
+            .collect();
+
+        let reference_latitude = if coordinates.is_empty() {
+            0.0
+        } else {
+            coordinates.iter().map(|(_, _, lat)| lat).sum::<f64>() / coordinates.len() as f64
+        };
+
+        let points = coordinates
+            .into_iter()
+            .map(|(stop_idx, lon, lat)| GeomWithData::new(Self::project(reference_latitude, lon, lat), stop_idx))
+            .collect();
+
+        SpatialIndex {
+            tree: RTree::bulk_load(points),
+            reference_latitude,
+        }
+    }
+
+    fn project(reference_latitude: f64, longitude: f64, latitude: f64) -> [f64; 2] {
+        [
+            EARTH_RADIUS * longitude.to_radians() * reference_latitude.to_radians().cos(),
+            EARTH_RADIUS * latitude.to_radians(),
+        ]
+    }
+
+    /// Iterates over all platforms with their distance (in meters), starting with the closest one
+    pub fn nearest_platforms(&self, longitude: f64, latitude: f64) -> impl Iterator<Item = (StopIdx, f64)> + '_ {
+        let location = Self::project(self.reference_latitude, longitude, latitude);
+        self.tree
+            .nearest_neighbor_iter_with_distance_2(&location)
+            .map(|(p, distance_2)| (p.data, distance_2.sqrt()))
+    }
+
+    /// Returns all platforms within the radius (in meters) with their distance
+    pub fn platforms_within(&self, longitude: f64, latitude: f64, radius: f64) -> impl Iterator<Item = (StopIdx, f64)> + '_ {
+        let location = Self::project(self.reference_latitude, longitude, latitude);
+        self.tree
+            .locate_within_distance(location, radius * radius)
+            .map(move |p| (p.data, p.geom().distance_2(&location).sqrt()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{HaversineDistance, Point};
+    use crate::model::Platform;
+    use super::SpatialIndex;
+
+    const CENTER: (f64, f64) = (17.1, 48.1);
+    /// Degrees of latitude and of longitude at the center per meter
+    const LATITUDE_PER_METER: f64 = 1.0 / 111_195.0;
+    const LONGITUDE_PER_METER: f64 = 1.0 / (111_195.0 * 0.667833);
+
+    fn platform(coordinates: Option<(f64, f64)>) -> Platform {
+        Platform {
+            id: String::new(),
+            name: String::new(),
+            station: 0,
+            longitude: coordinates.map(|(longitude, _)| longitude),
+            latitude: coordinates.map(|(_, latitude)| latitude),
+            platform_code: None,
+            zone_id: None,
+        }
+    }
+
+    /// The center, and platforms 100 m north, 299 m and 301 m east, 1 km south, and one without coordinates
+    fn platforms() -> Vec<Platform> {
+        let (lon, lat) = CENTER;
+        vec![
+            platform(Some((lon + 301.0 * LONGITUDE_PER_METER, lat))),
+            platform(Some((lon, lat - 1000.0 * LATITUDE_PER_METER))),
+            platform(None),
+            platform(Some(CENTER)),
+            platform(Some((lon, lat + 100.0 * LATITUDE_PER_METER))),
+            platform(Some((lon + 299.0 * LONGITUDE_PER_METER, lat))),
+        ]
+    }
+
+    fn haversine_distance(platform: &Platform) -> f64 {
+        let (lon, lat) = CENTER;
+        Point::new(lon, lat).haversine_distance(&Point::new(platform.longitude.unwrap(), platform.latitude.unwrap()))
+    }
+
+    #[test]
+    fn lists_the_nearest_platforms_first() {
+        let platforms = platforms();
+        let spatial_index = SpatialIndex::new(&platforms);
+        let nearest: Vec<_> = spatial_index.nearest_platforms(CENTER.0, CENTER.1).take(4).collect();
+
+        assert_eq!(nearest.iter().map(|&(stop, _)| stop).collect::<Vec<_>>(), [3, 4, 5, 0]);
+        // The planar distance stays within a meter of the great-circle one
+        for (stop, distance) in spatial_index.nearest_platforms(CENTER.0, CENTER.1) {
+            assert!((distance - haversine_distance(&platforms[stop as usize])).abs() < 1.0);
+        }
+        assert_eq!(spatial_index.nearest_platforms(CENTER.0, CENTER.1).count(), 5);
+    }
+
+    #[test]
+    fn finds_the_platforms_within_the_radius() {
+        let platforms = platforms();
+        let spatial_index = SpatialIndex::new(&platforms);
+        let within = |radius| {
+            let mut stops: Vec<_> = spatial_index.platforms_within(CENTER.0, CENTER.1, radius).map(|(stop, _)| stop).collect();
+            stops.sort();
+            stops
+        };
+
+        // 299 m and 301 m by the great-circle distance fall on the two sides of a 300 m radius
+        assert!(haversine_distance(&platforms[5]) < 300.0 && haversine_distance(&platforms[0]) > 300.0);
+        assert_eq!(within(300.0), [3, 4, 5]);
+        assert_eq!(within(1001.0), [0, 1, 3, 4, 5]);
+        assert_eq!(within(50.0), [3]);
+    }
+}
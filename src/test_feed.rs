@@ -0,0 +1,43 @@
+//! Small GTFS feeds of the tests, written to a temporary directory and loaded like a real feed
+
+use std::{fs, path::PathBuf, sync::atomic::{AtomicUsize, Ordering}};
+use gtfs_structures::Gtfs;
+use crate::{config::FeedContents, routing::{TransferOptions, WalkingOptions}, transit_index::TransitIndex};
+
+pub const AGENCY: &str = "agency_id,agency_name,agency_url,agency_timezone\nA,Agency,https://example.com,Europe/Bratislava\n";
+/// Service WD running on the weekdays of 2026
+pub const CALENDAR: &str = "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\nWD,1,1,1,1,1,0,0,20260101,20261231\n";
+
+/// Writes the files of a feed to a new temporary directory, agency.txt and calendar.txt are added unless given
+pub fn write(files: &[(&str, &str)]) -> PathBuf {
+    static FEEDS: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!("bimhd-test-feed-{}-{}", std::process::id(), FEEDS.fetch_add(1, Ordering::Relaxed)));
+    fs::create_dir_all(&path).unwrap();
+
+    let defaults = [("agency.txt", AGENCY), ("calendar.txt", CALENDAR)];
+    defaults
+        .iter()
+        .filter(|(name, _)| !files.iter().any(|(file_name, _)| file_name == name))
+        .chain(files)
+        .for_each(|(name, contents)| fs::write(path.join(name), contents).unwrap());
+
+    path
+}
+
+/// Loads the feed made of the files the same way as a feed directory
+pub fn load(files: &[(&str, &str)]) -> Gtfs {
+    let path = write(files);
+    let gtfs = FeedContents::Directory(path.clone()).parse().unwrap();
+    fs::remove_dir_all(&path).unwrap();
+    gtfs
+}
+
+/// Index of the feed, with footpaths up to 300 m and a minute to change vehicles
+pub fn index(files: &[(&str, &str)]) -> TransitIndex {
+    let transfers = TransferOptions {
+        min_change_time: 60,
+        mode_change_times: Default::default(),
+        station_change_times: Default::default(),
+    };
+    TransitIndex::new(&load(files), WalkingOptions { radius: 300.0, speed: 1.4 }, &transfers)
+}
@@ -1,337 +1,1021 @@
-use std::{cmp::Ordering, collections::{BTreeSet, HashMap, HashSet}, sync::Arc, time::{Instant, SystemTime}};
-use chrono::{DateTime, Local, Timelike};
+use std::{cmp::Reverse, collections::{BTreeSet, HashMap, HashSet}, time::Instant};
+use chrono::NaiveDate;
 use geo::{HaversineDistance, Point};
-use gtfs_structures::{Gtfs, Id, Stop, StopTime, Trip};
-use serde::Serialize;
-use trigram::similarity;
-
-#[derive(Serialize)]
+use gtfs_structures::{Gtfs, StopTransfer, TransferType};
+use serde::{Deserialize, Serialize};
+use crate::{
+    calendar::{ServiceCalendar, ServiceTime},
+    model::{Mode, ModeFilter, Platform, Route, RouteIdx, ServiceIdx, StationIdx, StopIdx, Trip, TripIdx},
+    realtime::{DelayOverlay, RealtimeTimes},
+    routing::{csa::{self, Connection}, mc_raptor, raptor, Access, Algorithm, Footpath, JourneyOptions, Leg, Place, RoutingQuery, ServiceDay, SortBy, TimeConstraint, TransferOptions, WalkingOptions},
+    search::{SearchMatch, StopSearch},
+    spatial_index::SpatialIndex,
+};
+
+/// Trip with its (arrival, departure) at every stop, used while building the patterns
+type TripTimes = (TripIdx, Vec<(u32, u32)>);
+
+/// All platforms sharing the same stop name
+#[derive(Serialize, Deserialize)]
 pub struct StopPlatforms {
     pub stop_name: String,
-    pub platforms: Vec<Arc<Stop>>,
-}
-
-impl StopPlatforms {
-    pub fn distance_to_location(&self, location: Point<f64>) -> f64 {
-        self.platforms.iter()
-            .map(|stop| {
-                let stop_location = Point::new(stop.longitude.unwrap_or(0.0), stop.latitude.unwrap_or(0.0));
-                location.haversine_distance(&stop_location)
-            })
-            .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
-            .map(|dist| format!("{:.2}", dist).parse::<f64>().unwrap())
-            .unwrap_or(f64::MAX)
-    }
+    pub platforms: Vec<StopIdx>,
 }
 
-#[derive(Serialize)]
+/// Part of a trip between the boarding and the alighting stop
 pub struct DirectTrip<'a> {
     pub trip: &'a Trip,
-    pub stop_times: &'a [StopTime],
+    pub route: &'a Route,
+    pub stops: Vec<&'a Platform>,
+    /// Service date of the trip, the times count from its start and exceed a day after midnight
+    pub service_date: NaiveDate,
+    pub departure_time: u32,
+    pub arrival_time: u32,
+    /// Present when there is a realtime update for the trip
+    pub predicted_departure_time: Option<u32>,
+    pub predicted_arrival_time: Option<u32>,
 }
 
 impl<'a> DirectTrip<'a> {
     pub fn get_stop_names(&self) -> Vec<&str> {
-        self.stop_times.iter().map(|st| st.stop.name.as_ref().unwrap().as_str()).collect()
+        self.stops.iter().map(|p| p.name.as_str()).collect()
     }
 
     pub fn get_duration(&self) -> u32 {
-        self.stop_times.last().unwrap().arrival_time.unwrap() - self.stop_times.first().unwrap().departure_time.unwrap()
+        self.arrival_time - self.departure_time
+    }
+
+    pub fn get_departure_time(&self) -> ServiceTime {
+        ServiceTime { date: self.service_date, seconds: self.departure_time }
+    }
+
+    pub fn get_arrival_time(&self) -> ServiceTime {
+        ServiceTime { date: self.service_date, seconds: self.arrival_time }
+    }
+
+    pub fn get_predicted_departure_time(&self) -> Option<ServiceTime> {
+        self.predicted_departure_time.map(|seconds| ServiceTime { date: self.service_date, seconds })
+    }
+
+    pub fn get_predicted_arrival_time(&self) -> Option<ServiceTime> {
+        self.predicted_arrival_time.map(|seconds| ServiceTime { date: self.service_date, seconds })
+    }
+}
+
+/// A single departure of a trip from a platform
+pub struct Departure<'a> {
+    pub trip: &'a Trip,
+    pub route: &'a Route,
+    pub platform: &'a Platform,
+    /// Service date of the trip, the times count from its start
+    pub service_date: NaiveDate,
+    pub departure_time: u32,
+    /// Present when there is a realtime update for the trip
+    pub predicted_departure_time: Option<u32>,
+    /// Headsign of the stop time, falling back to the trip headsign and the name of the last stop
+    pub headsign: &'a str,
+}
+
+impl<'a> Departure<'a> {
+    pub fn get_departure_time(&self) -> ServiceTime {
+        ServiceTime { date: self.service_date, seconds: self.departure_time }
     }
 
-    pub fn get_departure_time(&self) -> u32 {
-        self.stop_times.first().unwrap().departure_time.unwrap() % 86400
+    pub fn get_predicted_departure_time(&self) -> Option<ServiceTime> {
+        self.predicted_departure_time.map(|seconds| ServiceTime { date: self.service_date, seconds })
     }
+}
+
+/// Ordered sequence of stops shared by all trips of a route,
+/// which visit exactly the same stops in exactly the same order
+#[derive(Serialize, Deserialize)]
+pub struct TripPattern {
+    pub route: RouteIdx,
+    pub stops: Vec<StopIdx>,
+    /// Trips sorted by the departure time from the first stop. No trip
+    /// overtakes another one, so the trips are sorted at every stop
+    pub trips: Vec<TripIdx>,
+    /// Service of every trip, kept next to the trips so the routers do not have to look it up
+    pub services: Vec<ServiceIdx>,
+    /// (arrival, departure) at every stop of the pattern, trip after trip
+    stop_times: Vec<(u32, u32)>,
+}
 
-    pub fn get_arrival_time(&self) -> u32 {
-        self.stop_times.last().unwrap().arrival_time.unwrap() % 86400
+impl TripPattern {
+    pub fn arrival_at(&self, trip_idx: usize, position: usize) -> u32 {
+        self.stop_times[trip_idx * self.stops.len() + position].0
     }
 
-    pub fn get_real_arrival_time(&self) -> u32 {
-        if self.get_arrival_time() < self.get_departure_time() {
-            self.get_arrival_time() + 86400
-        } else {
-            self.get_arrival_time()
+    pub fn departure_at(&self, trip_idx: usize, position: usize) -> u32 {
+        self.stop_times[trip_idx * self.stops.len() + position].1
+    }
+
+    /// Times of the trips as scheduled
+    pub fn timetable(&self) -> Timetable<'_> {
+        Timetable { pattern: self, realtime: None }
+    }
+}
+
+/// Times of the trips of a pattern, as scheduled or with the realtime delays applied
+#[derive(Clone, Copy)]
+pub struct Timetable<'a> {
+    pub pattern: &'a TripPattern,
+    realtime: Option<&'a RealtimeTimes>,
+}
+
+impl<'a> Timetable<'a> {
+    pub fn with_realtime(pattern: &'a TripPattern, realtime: &'a RealtimeTimes) -> Self {
+        Timetable { pattern, realtime: Some(realtime) }
+    }
+
+    pub fn arrival_at(&self, trip_idx: usize, position: usize) -> u32 {
+        match self.realtime {
+            Some(realtime) => realtime.stop_times[trip_idx * self.pattern.stops.len() + position].0,
+            None => self.pattern.arrival_at(trip_idx, position),
+        }
+    }
+
+    pub fn departure_at(&self, trip_idx: usize, position: usize) -> u32 {
+        match self.realtime {
+            Some(realtime) => realtime.stop_times[trip_idx * self.pattern.stops.len() + position].1,
+            None => self.pattern.departure_at(trip_idx, position),
         }
     }
+
+    /// Whether the times of the trip come from a realtime update
+    pub fn is_predicted(&self, trip_idx: usize) -> bool {
+        self.realtime.is_some_and(|realtime| realtime.updated_trips[trip_idx])
+    }
+
+    /// Whether the trip can be boarded or left at the position, cancelled trips and skipped stops can not
+    pub fn stops_at(&self, trip_idx: usize, position: usize) -> bool {
+        self.realtime.is_none_or(|realtime| !realtime.skipped_stops[trip_idx * self.pattern.stops.len() + position])
+    }
+
+    /// Returns the first active trip departing from the stop at the given position at or after the time
+    pub fn earliest_trip(&self, position: usize, time: u32, is_active: impl Fn(ServiceIdx) -> bool) -> Option<usize> {
+        if !self.is_sorted() {
+            return (0..self.pattern.trips.len())
+                .filter(|&trip_idx| self.departure_at(trip_idx, position) >= time && is_active(self.pattern.services[trip_idx]))
+                .filter(|&trip_idx| self.stops_at(trip_idx, position))
+                .min_by_key(|&trip_idx| self.departure_at(trip_idx, position));
+        }
+
+        let first_trip = self.partition_point(|trip_idx| self.departure_at(trip_idx, position) < time);
+        (first_trip..self.pattern.trips.len()).find(|&trip_idx| is_active(self.pattern.services[trip_idx]) && self.stops_at(trip_idx, position))
+    }
+
+    /// Returns the last active trip arriving at the stop at the given position at or before the time
+    pub fn latest_trip(&self, position: usize, time: u32, is_active: impl Fn(ServiceIdx) -> bool) -> Option<usize> {
+        if !self.is_sorted() {
+            return (0..self.pattern.trips.len())
+                .filter(|&trip_idx| self.arrival_at(trip_idx, position) <= time && is_active(self.pattern.services[trip_idx]))
+                .filter(|&trip_idx| self.stops_at(trip_idx, position))
+                .max_by_key(|&trip_idx| self.arrival_at(trip_idx, position));
+        }
+
+        let first_late_trip = self.partition_point(|trip_idx| self.arrival_at(trip_idx, position) <= time);
+        (0..first_late_trip).rev().find(|&trip_idx| is_active(self.pattern.services[trip_idx]) && self.stops_at(trip_idx, position))
+    }
+
+    /// Returns the trips departing from the stop at the given position at or after the time, ordered by the departure
+    pub fn trips_departing_from(&self, position: usize, time: u32) -> Vec<usize> {
+        if self.is_sorted() {
+            let first_trip = self.partition_point(|trip_idx| self.departure_at(trip_idx, position) < time);
+            return (first_trip..self.pattern.trips.len()).filter(|&trip_idx| self.stops_at(trip_idx, position)).collect();
+        }
+
+        let mut trips: Vec<usize> = (0..self.pattern.trips.len())
+            .filter(|&trip_idx| self.departure_at(trip_idx, position) >= time && self.stops_at(trip_idx, position))
+            .collect();
+        trips.sort_by_key(|&trip_idx| self.departure_at(trip_idx, position));
+        trips
+    }
+
+    /// Scheduled trips never overtake each other, delayed ones can
+    fn is_sorted(&self) -> bool {
+        self.realtime.is_none_or(|realtime| realtime.sorted)
+    }
+
+    /// Binary search over the trips, which are sorted at every stop
+    fn partition_point(&self, predicate: impl Fn(usize) -> bool) -> usize {
+        let (mut low, mut high) = (0, self.pattern.trips.len());
+
+        while low < high {
+            let middle = (low + high) / 2;
+            if predicate(middle) {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        low
+    }
 }
 
-pub struct TransitIndex<'a> {
-    gtfs: &'a Gtfs,
-    pub platforms: HashMap<&'a str, Arc<StopPlatforms>>,
-    pub direct_trips: HashMap<(&'a str, &'a str), Vec<Arc<DirectTrip<'a>>>>,
-    pub distances: HashMap<(&'a str, &'a str), f64>,
-    pub stops_graph: HashMap<&'a str, HashMap<&'a str, Vec<Arc<DirectTrip<'a>>>>>,
+/// Owns everything needed to answer queries, so a single index can be shared between
+/// the worker threads. Stops, routes and trips are stored in vectors and refer to each
+/// other by their position, the GTFS ids are resolved through lookup tables while building
+#[derive(Serialize, Deserialize)]
+pub struct TransitIndex {
+    pub platforms: Vec<Platform>,
+    /// Platforms grouped by their stop name
+    pub stations: Vec<StopPlatforms>,
+    /// Names of the stations, normalised for the search
+    pub stop_search: StopSearch,
+    pub routes: Vec<Route>,
+    pub trips: Vec<Trip>,
+    /// Trip id -> index into trips
+    pub trip_indices: HashMap<String, TripIdx>,
+    /// For every trip, stop_sequence of its stop times, so realtime updates can be matched to positions within the pattern
+    pub stop_sequences: Vec<Vec<u16>>,
+    /// (trip, position of the stop time) -> headsign, only for stop times with their own headsign
+    pub stop_headsigns: HashMap<(TripIdx, u32), String>,
+    pub patterns: Vec<TripPattern>,
+    /// Trips of the patterns split into connections between consecutive stops, sorted by the departure
+    pub connections: Vec<Connection>,
+    /// For every platform, (index into patterns, position of the first visit of the platform within the pattern)
+    pub stop_patterns: Vec<Vec<(usize, usize)>>,
+    /// For every trip, (index into patterns, position of the trip within the pattern),
+    /// `None` for trips which can not be routed on
+    pub trip_patterns: Vec<Option<(usize, usize)>>,
+    pub spatial_index: SpatialIndex,
+    pub calendar: ServiceCalendar,
+    pub footpaths: Vec<Footpath>,
+    /// For every platform, indices into footpaths of footpaths leaving the platform
+    pub outgoing_footpaths: Vec<Vec<usize>>,
+    /// For every platform, indices into footpaths of footpaths arriving at the platform
+    pub incoming_footpaths: Vec<Vec<usize>>,
+    /// For every platform, minimum time between arriving with one vehicle and departing with
+    /// another one, `None` where changing vehicles is not possible
+    pub change_times: Vec<Option<u32>>,
+    /// For every trip, the trip its vehicle continues as from the platform where it ends
+    pub block_successors: Vec<Option<TripIdx>>,
+    /// For every trip, the trip its vehicle came as to the platform where it starts
+    pub block_predecessors: Vec<Option<TripIdx>>,
+    pub walking: WalkingOptions,
+    pub transfers: TransferOptions,
 }
 
-impl<'a> TransitIndex<'a> {
-    pub fn new(gtfs: &'a Gtfs) -> Self {
-        let mut transit_index = TransitIndex {
-            gtfs,
-            platforms: Self::build_platforms(gtfs),
-            direct_trips: Self::build_direct_trips(gtfs),
-            distances: Self::build_distances(gtfs),
-            stops_graph: HashMap::new(),
-        };
+impl TransitIndex {
+    pub fn new(gtfs: &Gtfs, walking: WalkingOptions, transfers: &TransferOptions) -> Self {
+        let (platforms, stations) = Self::build_platforms(gtfs);
+        let stop_search = StopSearch::new(stations.iter().map(|station| station.stop_name.as_str()));
+        let stop_indices = Self::build_lookup(platforms.iter().map(|p| p.id.as_str()));
+        let routes = Self::build_routes(gtfs);
+        let route_indices = Self::build_lookup(routes.iter().map(|r| r.id.as_str()));
+        let calendar = ServiceCalendar::new(gtfs);
+        let trips = Self::build_trips(gtfs, &route_indices, &calendar);
+        let trip_indices = Self::build_lookup(trips.iter().map(|t| t.id.as_str()));
+
+        let stop_headsigns = gtfs.trips
+            .values()
+            .filter_map(|trip| Some((trip_indices.get(&trip.id).copied()?, trip)))
+            .flat_map(|(trip_idx, trip)| {
+                trip.stop_times.iter().enumerate().filter_map(move |(position, st)| {
+                    st.stop_headsign.clone().map(|headsign| ((trip_idx, position as u32), headsign))
+                })
+            })
+            .collect();
+
+        let mut stop_sequences = vec![Vec::new(); trips.len()];
+        gtfs.trips.values().for_each(|trip| {
+            if let Some(&trip_idx) = trip_indices.get(&trip.id) {
+                stop_sequences[trip_idx as usize] = trip.stop_times.iter().map(|st| st.stop_sequence).collect();
+            }
+        });
 
-        // Build of an index used for a quick direct trip lookup between two stops
-        transit_index.direct_trips.keys().into_iter().for_each(|(from, to)| {
-            let first_entry = transit_index.stops_graph.entry(from).or_insert(HashMap::new());
-            let second_entry = first_entry.entry(to).or_insert(Vec::new());
+        let patterns = Self::build_patterns(gtfs, &stop_indices, &trip_indices, &trips);
+        let stop_patterns = Self::build_stop_patterns(&patterns, platforms.len());
+        let connections = csa::build_connections(&patterns);
 
-            if let Some(trip) = transit_index.direct_trips.get(&(from, to)) {
-                second_entry.extend(trip.clone())
+        let mut trip_patterns = vec![None; trips.len()];
+        patterns.iter().enumerate().for_each(|(pattern_idx, pattern)| {
+            pattern.trips.iter().enumerate().for_each(|(trip_row, &trip_idx)| {
+                trip_patterns[trip_idx as usize] = Some((pattern_idx, trip_row));
+            });
+        });
+        let spatial_index = SpatialIndex::new(&platforms);
+        let transfer_rules = Self::build_transfer_rules(gtfs, &stop_indices);
+        let footpaths = Self::build_footpaths(&transfer_rules, &platforms, &spatial_index, walking);
+
+        let mut outgoing_footpaths: Vec<Vec<usize>> = vec![Vec::new(); platforms.len()];
+        let mut incoming_footpaths: Vec<Vec<usize>> = vec![Vec::new(); platforms.len()];
+        footpaths.iter().enumerate().for_each(|(footpath_idx, footpath)| {
+            outgoing_footpaths[footpath.from_stop as usize].push(footpath_idx);
+            incoming_footpaths[footpath.to_stop as usize].push(footpath_idx);
+        });
+
+        let change_times = Self::build_change_times(&transfer_rules, &platforms, &stations, &routes, &patterns, transfers);
+        let block_successors = Self::build_block_successors(gtfs, &trip_indices, &trips, &patterns, &trip_patterns);
+        let mut block_predecessors = vec![None; trips.len()];
+        block_successors.iter().enumerate().for_each(|(trip_idx, successor)| {
+            if let Some(successor) = successor {
+                block_predecessors[*successor as usize] = Some(trip_idx as TripIdx);
             }
         });
 
-        transit_index
+        TransitIndex {
+            platforms,
+            stations,
+            stop_search,
+            routes,
+            trips,
+            trip_indices,
+            stop_sequences,
+            stop_headsigns,
+            patterns,
+            connections,
+            stop_patterns,
+            trip_patterns,
+            spatial_index,
+            calendar,
+            footpaths,
+            outgoing_footpaths,
+            incoming_footpaths,
+            change_times,
+            block_successors,
+            block_predecessors,
+            walking,
+            transfers: transfers.clone(),
+        }
+    }
+
+    /// Maps every id to its position
+    fn build_lookup<'i>(ids: impl Iterator<Item = &'i str>) -> HashMap<String, u32> {
+        ids.enumerate().map(|(idx, id)| (id.to_string(), idx as u32)).collect()
     }
 
-    fn build_platforms(gtfs: &'a Gtfs) -> HashMap<&str, Arc<StopPlatforms>> {
+    fn build_platforms(gtfs: &Gtfs) -> (Vec<Platform>, Vec<StopPlatforms>) {
         // Make an array of unique stop names. BTreeSet was used to
         // always have the same order of elements in set
         let stop_names = gtfs.stops
             .values()
-            .map(|s| s.as_ref().name.as_ref().unwrap().as_str())
+            .map(|s| s.name.as_deref().unwrap_or_default())
             .collect::<BTreeSet<&str>>();
-        
-        // This map serves both as an index for stop_id -> stop (and its platforms),
-        // and as a storage for all grouped stop platforms
-        let mut stop_platforms: HashMap<&str, Arc<StopPlatforms>> = HashMap::new();
-
-        stop_names.iter().for_each(|stop_name| {
-            // Get all stop platforms for a given stop name
-            let platforms: Vec<Arc<Stop>> = gtfs.stops
-                .values()
-                .filter_map(|s| {
-                    if s.as_ref().name.as_ref().is_some_and(|n| n.as_str() == *stop_name) {
-                        Some((*s).clone())
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            
-            let current_stop_platform = Arc::new(StopPlatforms {
-                stop_name: stop_name.to_string(),
-                platforms,
-            });
 
-            gtfs.stops.values().for_each(|s| {
-                if s.as_ref().name.as_ref().is_some_and(|n| n.as_str() == *stop_name) {
-                    stop_platforms.insert(s.id(), current_stop_platform.clone());
+        let station_indices: HashMap<&str, StationIdx> = stop_names
+            .iter()
+            .enumerate()
+            .map(|(station_idx, &stop_name)| (stop_name, station_idx as StationIdx))
+            .collect();
+
+        // Sorted by the stop id, so the platform indices are stable between runs
+        let mut stops: Vec<_> = gtfs.stops.values().collect();
+        stops.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let platforms: Vec<Platform> = stops
+            .into_iter()
+            .map(|s| {
+                let name = s.name.clone().unwrap_or_default();
+                Platform {
+                    id: s.id.clone(),
+                    station: station_indices[name.as_str()],
+                    name,
+                    longitude: s.longitude,
+                    latitude: s.latitude,
+                    platform_code: s.platform_code.clone(),
+                    zone_id: s.zone_id.clone(),
                 }
-            });
+            })
+            .collect();
+
+        let mut stations: Vec<StopPlatforms> = stop_names
+            .into_iter()
+            .map(|stop_name| StopPlatforms { stop_name: stop_name.to_string(), platforms: Vec::new() })
+            .collect();
+
+        platforms.iter().enumerate().for_each(|(stop_idx, platform)| {
+            stations[platform.station as usize].platforms.push(stop_idx as StopIdx);
         });
 
-        stop_platforms
+        (platforms, stations)
+    }
+
+    fn build_routes(gtfs: &Gtfs) -> Vec<Route> {
+        let mut routes: Vec<Route> = gtfs.routes
+            .values()
+            .map(|r| Route {
+                id: r.id.clone(),
+                short_name: r.short_name.clone(),
+                mode: Mode::from(r.route_type),
+            })
+            .collect();
+
+        routes.sort_by(|a, b| a.id.cmp(&b.id));
+        routes
     }
 
-    fn build_direct_trips(gtfs: &'a Gtfs) -> HashMap<(&str, &str), Vec<Arc<DirectTrip>>> {
-        println!("[i] Building primary stop_id -> trips[] index");
+    /// Trips of unknown routes are skipped
+    fn build_trips(gtfs: &Gtfs, route_indices: &HashMap<String, RouteIdx>, calendar: &ServiceCalendar) -> Vec<Trip> {
+        let mut trips: Vec<Trip> = gtfs.trips
+            .values()
+            .filter_map(|t| Some(Trip {
+                id: t.id.clone(),
+                route: *route_indices.get(&t.route_id)?,
+                service: calendar.get_service_idx(&t.service_id)?,
+                headsign: t.trip_headsign.clone(),
+            }))
+            .collect();
+
+        trips.sort_by(|a, b| a.id.cmp(&b.id));
+        trips
+    }
+
+    fn build_patterns(
+        gtfs: &Gtfs,
+        stop_indices: &HashMap<String, StopIdx>,
+        trip_indices: &HashMap<String, TripIdx>,
+        trips: &[Trip],
+    ) -> Vec<TripPattern> {
+        println!("[i] Building trip patterns index");
         let start = Instant::now();
-        
-        // First index used for indexing stops and corresponding trips, which do include
-        // target stops. This is especially helpful for the build up of a second index
-        let mut singular_trips_index: HashMap<&str, HashSet<&str>> = HashMap::new();
-
-        gtfs.stops.values().for_each(|s| {
-            let direct_trips: HashSet<&str> = gtfs.trips
-                .values()
-                .filter_map(|t| t.stop_times.iter().any(|st| st.stop.id() == s.id()).then_some(t.id.as_str()))
+
+        // Trips of the same route which stop at the same stops are grouped together,
+        // so the stop sequence is stored only once per pattern instead of once per trip.
+        // Trips with a stop lacking both arrival and departure time can not be routed on
+        let mut grouped_trips: HashMap<(RouteIdx, Vec<StopIdx>), Vec<TripTimes>> = HashMap::new();
+
+        gtfs.trips.values().for_each(|trip| {
+            let Some(&trip_idx) = trip_indices.get(&trip.id) else {
+                return;
+            };
+
+            let stop_times: Option<Vec<(u32, u32)>> = trip.stop_times
+                .iter()
+                .map(|st| Some((st.arrival_time.or(st.departure_time)?, st.departure_time.or(st.arrival_time)?)))
+                .collect();
+
+            let stops: Option<Vec<StopIdx>> = trip.stop_times
+                .iter()
+                .map(|st| stop_indices.get(&st.stop.id).copied())
                 .collect();
 
-            singular_trips_index.insert(s.id(), direct_trips);
+            if let (Some(stop_times), Some(stops)) = (stop_times, stops) {
+                if !stops.is_empty() {
+                    grouped_trips.entry((trips[trip_idx as usize].route, stops)).or_default().push((trip_idx, stop_times));
+                }
+            }
         });
 
-        println!("[i] Done; Took {} s", start.elapsed().as_secs());
+        let mut patterns: Vec<TripPattern> = grouped_trips
+            .into_iter()
+            .flat_map(|((route, stops), mut pattern_trips)| {
+                pattern_trips.sort_by_key(|(_, stop_times)| stop_times[0].1);
+                Self::split_overtaking_trips(pattern_trips)
+                    .into_iter()
+                    .map(move |group| TripPattern {
+                        route,
+                        stops: stops.clone(),
+                        trips: group.iter().map(|(trip_idx, _)| *trip_idx).collect(),
+                        services: group.iter().map(|(trip_idx, _)| trips[*trip_idx as usize].service).collect(),
+                        stop_times: group.into_iter().flat_map(|(_, stop_times)| stop_times).collect(),
+                    })
+            })
+            .collect();
 
-        println!("[i] Building secondary (stop_id, stop_id) -> trips[] index");
-        let start = Instant::now();
+        // HashMap iteration order is random, keep the pattern indices stable between runs
+        patterns.sort_by(|a, b| (a.route, &a.stops, a.trips[0]).cmp(&(b.route, &b.stops, b.trips[0])));
 
-        // Secondary index used for quick lookups for direct trips between two stops
-        let mut trips_index: HashMap<(&str, &str), Vec<Arc<DirectTrip>>> = HashMap::new();
-        
-        gtfs.stops.values().for_each(|from| {
-            gtfs.stops.values().for_each(|to| {
-                if from.id() == to.id() {
-                    return ();
-                }
+        println!("[i] Done; {} patterns; Took {} s", patterns.len(), start.elapsed().as_secs());
+        patterns
+    }
 
-                let Some(trips_from) = singular_trips_index.get(from.id()) else {
-                    return ();
-                };
-                
-                let Some(trips_to) = singular_trips_index.get(to.id()) else {
-                    return ();
-                };
-                
-                let direct_trips: Vec<Arc<DirectTrip>> = trips_from
-                    .intersection(trips_to)
-                    .filter_map(|t| {
-                        let Some(trip) = gtfs.get_trip(t).ok() else {
-                            return None;
-                        };
-                        
-                        let Some(from_idx) = trip.stop_times.iter().position(|st| st.stop.id() == from.id()) else {
-                            return None;
-                        };
-
-                        let Some(to_idx) = trip.stop_times.iter().position(|st| st.stop.id() == to.id()) else {
-                            return None;
-                        };
-                        
-                        if from_idx < to_idx {
-                            return Some(Arc::new(DirectTrip {
-                                trip,   
-                                stop_times: &trip.stop_times[from_idx..to_idx + 1],
-                            }))
-                        }
-
-                        None
-                    }).collect();
-                
-                if direct_trips.len() > 0 {
-                    trips_index.insert((from.id(), to.id()), direct_trips);
+    /// Routing expects trips of a pattern to never overtake each other. Trips sorted by
+    /// their first departure are split into groups, where this holds for every stop
+    fn split_overtaking_trips(trips: Vec<TripTimes>) -> Vec<Vec<TripTimes>> {
+        let overtakes = |previous: &[(u32, u32)], trip: &[(u32, u32)]| {
+            previous.iter().zip(trip.iter()).any(|(p, t)| t.0 < p.0 || t.1 < p.1)
+        };
+
+        let mut groups: Vec<Vec<TripTimes>> = Vec::new();
+
+        trips.into_iter().for_each(|trip| {
+            match groups.iter_mut().find(|g| !overtakes(&g.last().unwrap().1, &trip.1)) {
+                Some(group) => group.push(trip),
+                None => groups.push(vec![trip]),
+            }
+        });
+
+        groups
+    }
+
+    fn build_stop_patterns(patterns: &[TripPattern], platform_count: usize) -> Vec<Vec<(usize, usize)>> {
+        let mut stop_patterns: Vec<Vec<(usize, usize)>> = vec![Vec::new(); platform_count];
+
+        patterns.iter().enumerate().for_each(|(pattern_idx, pattern)| {
+            pattern.stops.iter().enumerate().for_each(|(position, &stop)| {
+                let entry = &mut stop_patterns[stop as usize];
+
+                // Patterns with loops visit some stops twice, only the first visit is indexed
+                if entry.last().is_none_or(|&(last_idx, _)| last_idx != pattern_idx) {
+                    entry.push((pattern_idx, position));
                 }
             });
         });
 
-        println!("[i] Done; Took {} s", start.elapsed().as_secs());
+        stop_patterns
+    }
+
+    /// Rules of transfers.txt as (from platform, to platform, rule). A rule of a parent station applies
+    /// to each of its platforms, and the rules naming platforms come after the ones naming their stations,
+    /// so applying the rules in order lets the more specific ones win
+    fn build_transfer_rules<'g>(gtfs: &'g Gtfs, stop_indices: &HashMap<String, StopIdx>) -> Vec<(StopIdx, StopIdx, &'g StopTransfer)> {
+        let mut children: HashMap<&str, Vec<StopIdx>> = HashMap::new();
+        gtfs.stops.values().for_each(|stop| {
+            if let (Some(parent_station), Some(&stop_idx)) = (&stop.parent_station, stop_indices.get(&stop.id)) {
+                children.entry(parent_station.as_str()).or_default().push(stop_idx);
+            }
+        });
+
+        // (platforms named by the stop id, whether it is a station)
+        let platforms_of = |stop_id: &str| match children.get(stop_id) {
+            Some(platforms) => Some((platforms.clone(), true)),
+            None => stop_indices.get(stop_id).map(|&stop_idx| (vec![stop_idx], false)),
+        };
+
+        // (number of stations named by the rule, from platform, to platform, rule)
+        let mut rules: Vec<(u8, StopIdx, StopIdx, &StopTransfer)> = Vec::new();
+        gtfs.stops.values().for_each(|stop| {
+            stop.transfers.iter().for_each(|transfer| {
+                let (Some((from_stops, from_station)), Some((to_stops, to_station))) = (platforms_of(&stop.id), platforms_of(&transfer.to_stop_id)) else {
+                    return;
+                };
+
+                let stations = u8::from(from_station) + u8::from(to_station);
+                from_stops.iter().for_each(|&from_stop| {
+                    rules.extend(to_stops.iter().map(|&to_stop| (stations, from_stop, to_stop, transfer)));
+                });
+            });
+        });
 
-        trips_index
+        rules.sort_by_key(|&(stations, from_stop, to_stop, _)| (Reverse(stations), from_stop, to_stop));
+        rules.into_iter().map(|(_, from_stop, to_stop, transfer)| (from_stop, to_stop, transfer)).collect()
     }
 
-    fn build_distances(gtfs: &'a Gtfs) -> HashMap<(&str, &str), f64> {
-        println!("[i] Building stop_id -> stop_id -> distance index");
+    fn build_footpaths(
+        transfer_rules: &[(StopIdx, StopIdx, &StopTransfer)],
+        platforms: &[Platform],
+        spatial_index: &SpatialIndex,
+        walking: WalkingOptions,
+    ) -> Vec<Footpath> {
+        println!("[i] Building footpaths index");
         let start = Instant::now();
 
-        // Secondary index used for quick lookups for distances between two stops
-        let mut distances: HashMap<(&str, &str), f64> = HashMap::new();
-        gtfs.stops.values().for_each(|from| {
-            gtfs.stops.values().for_each(|to| {
-                if from.id() == to.id() || distances.contains_key(&(to.id(), from.id())) || distances.contains_key(&(from.id(), to.id())) {
-                    return ();
+        let mut footpaths: HashMap<(StopIdx, StopIdx), Footpath> = HashMap::new();
+
+        // Every pair of platforms within the walking radius is connected by a footpath
+        platforms.iter().enumerate().for_each(|(from_stop, from)| {
+            let from_stop = from_stop as StopIdx;
+            let (Some(longitude), Some(latitude)) = (from.longitude, from.latitude) else {
+                return;
+            };
+
+            spatial_index.platforms_within(longitude, latitude, walking.radius).for_each(|(to_stop, distance)| {
+                if to_stop != from_stop {
+                    footpaths.insert((from_stop, to_stop), Footpath {
+                        from_stop,
+                        to_stop,
+                        duration: walking.duration(distance),
+                        distance,
+                        guaranteed: false,
+                    });
                 }
+            });
+        });
 
-                let from_location = Point::new(from.longitude.unwrap_or(0.0), from.latitude.unwrap_or(0.0));
-                let to_location = Point::new(to.longitude.unwrap_or(0.0), to.latitude.unwrap_or(0.0));
-                let distance = from_location.haversine_distance(&to_location);
+        // Transfers from transfers.txt take precedence over the computed footpaths,
+        // transfers within a single platform are not footpaths and are skipped.
+        // Transfers restricted to some trips or routes were dropped when parsing the feed
+        transfer_rules.iter().for_each(|&(from_stop, to_stop, transfer)| {
+            if to_stop == from_stop {
+                return;
+            }
 
-                distances.insert((to.id(), from.id()), distance);
-                distances.insert((from.id(), to.id()), distance);
+            if transfer.transfer_type == TransferType::Impossible {
+                footpaths.remove(&(from_stop, to_stop));
+                return;
+            }
+
+            let (from, to) = (&platforms[from_stop as usize], &platforms[to_stop as usize]);
+            let distance = match (from.longitude, from.latitude, to.longitude, to.latitude) {
+                (Some(from_lon), Some(from_lat), Some(to_lon), Some(to_lat)) => {
+                    Point::new(from_lon, from_lat).haversine_distance(&Point::new(to_lon, to_lat))
+                },
+                _ => 0.0,
+            };
+
+            // A minimum time transfer takes at least as long as the walk. Staying on board or having
+            // to alight concerns the trips of a block rather than walks between platforms
+            let walk_duration = walking.duration(distance);
+            let duration = match transfer.transfer_type {
+                TransferType::Recommended | TransferType::Timed => transfer.min_transfer_time.unwrap_or(walk_duration),
+                TransferType::MinTime => transfer.min_transfer_time.map_or(walk_duration, |min_time| min_time.max(walk_duration)),
+                TransferType::Impossible | TransferType::StayOnBoard | TransferType::MustAlight => return,
+            };
+
+            footpaths.insert((from_stop, to_stop), Footpath {
+                from_stop,
+                to_stop,
+                duration,
+                distance,
+                guaranteed: transfer.transfer_type == TransferType::Timed,
             });
         });
-        println!("[i] Done; Took {} s", start.elapsed().as_secs());
-        distances
-    }
 
-    pub fn search_by_name(&self, query: &str) -> Vec<Arc<StopPlatforms>> {
-        // (weight, stop name, vector of all stops / platforms for a given stop name)
-        let mut weighted_stop_names: Vec<(f32, Arc<StopPlatforms>)> = self.platforms
-            .values()
-            .map(|sp| (similarity(&sp.stop_name, query), sp.clone()))
-            .collect();
+        let mut footpaths: Vec<Footpath> = footpaths.into_values().collect();
+        footpaths.sort_by_key(|f| (f.from_stop, f.to_stop));
 
-        // Move the result with higher score closer to the beginning of an array
-        weighted_stop_names.sort_by(|a, b| b.0.total_cmp(&a.0));
-        weighted_stop_names.iter().map(|f| f.1.clone()).collect()
+        println!("[i] Done; {} footpaths; Took {} s", footpaths.len(), start.elapsed().as_secs());
+        footpaths
     }
 
-    pub fn find_nearest_stops(&self, longitude: f64, latitude: f64, count: usize) -> Vec<Arc<StopPlatforms>> {
-        let location = Point::new(longitude, latitude);
-        let mut unique_stops = HashSet::new();
-        let mut results = Vec::new();
-    
-        let mut distances: Vec<(f64, Arc<StopPlatforms>)> = self.platforms
-            .values()
-            .map(|sp| {
-                let distance = sp.distance_to_location(location);
-                (distance, Arc::clone(sp))
+    /// Minimum change time of every platform: the one of a transfer within the platform (or its station) from transfers.txt,
+    /// else the one configured for its stop, else the longest one of the modes serving it
+    fn build_change_times(
+        transfer_rules: &[(StopIdx, StopIdx, &StopTransfer)],
+        platforms: &[Platform],
+        stations: &[StopPlatforms],
+        routes: &[Route],
+        patterns: &[TripPattern],
+        transfers: &TransferOptions,
+    ) -> Vec<Option<u32>> {
+        let mut mode_change_times: Vec<Option<u32>> = vec![None; platforms.len()];
+        patterns.iter().for_each(|pattern| {
+            let mode = routes[pattern.route as usize].mode;
+            let change_time = transfers.mode_change_times[mode as usize].unwrap_or(transfers.min_change_time);
+
+            pattern.stops.iter().for_each(|&stop| {
+                let entry = &mut mode_change_times[stop as usize];
+                *entry = Some(entry.map_or(change_time, |other| other.max(change_time)));
+            });
+        });
+
+        let mut change_times: Vec<Option<u32>> = platforms
+            .iter()
+            .zip(mode_change_times)
+            .map(|(platform, mode_change_time)| {
+                let station_change_time = transfers.station_change_times.get(&stations[platform.station as usize].stop_name);
+                Some(station_change_time.copied().or(mode_change_time).unwrap_or(transfers.min_change_time))
             })
             .collect();
-    
-        distances.sort_by(|a, b| a.0.total_cmp(&b.0));
-    
-        for (_, platform) in distances {
-            if unique_stops.insert(platform.stop_name.clone()) {
-                results.push(platform);
-                if results.len() == count {
-                    break;
+
+        transfer_rules.iter().filter(|(from_stop, to_stop, _)| from_stop == to_stop).for_each(|&(stop, _, transfer)| {
+            let change_time = &mut change_times[stop as usize];
+            match transfer.transfer_type {
+                TransferType::Timed => *change_time = Some(0),
+                TransferType::MinTime => *change_time = transfer.min_transfer_time.or(*change_time),
+                TransferType::Impossible => *change_time = None,
+                _ => {},
+            }
+        });
+
+        change_times
+    }
+
+    /// Chains the trips sharing a block_id and the service, ordered by their departure. The vehicle
+    /// continues as the next trip when it starts at the platform where the trip ends, after it arrives
+    fn build_block_successors(
+        gtfs: &Gtfs,
+        trip_indices: &HashMap<String, TripIdx>,
+        trips: &[Trip],
+        patterns: &[TripPattern],
+        trip_patterns: &[Option<(usize, usize)>],
+    ) -> Vec<Option<TripIdx>> {
+        let mut blocks: HashMap<(&str, ServiceIdx), Vec<TripIdx>> = HashMap::new();
+        gtfs.trips.values().for_each(|trip| {
+            if let (Some(block_id), Some(&trip_idx)) = (&trip.block_id, trip_indices.get(&trip.id)) {
+                if trip_patterns[trip_idx as usize].is_some() {
+                    blocks.entry((block_id.as_str(), trips[trip_idx as usize].service)).or_default().push(trip_idx);
                 }
             }
+        });
+
+        // (pattern, trip within the pattern) of a routable trip
+        let row = |trip_idx: TripIdx| trip_patterns[trip_idx as usize].map(|(pattern_idx, trip_row)| (&patterns[pattern_idx], trip_row)).unwrap();
+
+        let mut successors = vec![None; trips.len()];
+        blocks.into_values().for_each(|mut block| {
+            block.sort_by_key(|&trip_idx| {
+                let (pattern, trip_row) = row(trip_idx);
+                (pattern.departure_at(trip_row, 0), trip_idx)
+            });
+
+            block.windows(2).for_each(|pair| {
+                let ((pattern, trip_row), (next_pattern, next_row)) = (row(pair[0]), row(pair[1]));
+                let last_position = pattern.stops.len() - 1;
+
+                if pattern.stops[last_position] == next_pattern.stops[0]
+                    && pattern.arrival_at(trip_row, last_position) <= next_pattern.departure_at(next_row, 0)
+                {
+                    successors[pair[0] as usize] = Some(pair[1]);
+                }
+            });
+        });
+
+        successors
+    }
+
+    /// Returns the stop with the most similar name, or `None` when no stop name is similar enough
+    pub fn find_stop(&self, query: &str) -> Option<&StopPlatforms> {
+        self.search_stops(query).into_iter().next().map(|(stop_platforms, _)| stop_platforms)
+    }
+
+    /// Returns the stops matching the query, the best match first
+    pub fn search_stops(&self, query: &str) -> Vec<(&StopPlatforms, SearchMatch)> {
+        self.stop_search
+            .search(query)
+            .into_iter()
+            .map(|search_match| (&self.stations[search_match.position], search_match))
+            .collect()
+    }
+
+    pub fn find_nearest_stops(&self, longitude: f64, latitude: f64, count: usize) -> Vec<&StopPlatforms> {
+        let mut unique_stations = HashSet::new();
+
+        // Platforms come ordered by their distance, so the first platform
+        // of every station is also the closest platform of that station
+        self.spatial_index
+            .nearest_platforms(longitude, latitude)
+            .map(|(stop, _)| self.platforms[stop as usize].station)
+            .filter(|&station| unique_stations.insert(station))
+            .take(count)
+            .map(|station| &self.stations[station as usize])
+            .collect()
+    }
+
+    /// Distance in meters from the location to the closest platform of the stop
+    pub fn distance_to_location(&self, stop_platforms: &StopPlatforms, location: Point<f64>) -> f64 {
+        stop_platforms.platforms
+            .iter()
+            .map(|&stop| {
+                let platform = &self.platforms[stop as usize];
+                let platform_location = Point::new(platform.longitude.unwrap_or(0.0), platform.latitude.unwrap_or(0.0));
+                location.haversine_distance(&platform_location)
+            })
+            .min_by(|a, b| a.total_cmp(b))
+            .map(|dist| format!("{:.2}", dist).parse::<f64>().unwrap())
+            .unwrap_or(f64::MAX)
+    }
+
+    /// Returns every pattern passing through the platform,
+    /// together with the position of the platform within the pattern
+    pub fn get_patterns_through(&self, stop: StopIdx) -> impl Iterator<Item = (&TripPattern, usize)> {
+        self.stop_patterns[stop as usize]
+            .iter()
+            .map(|&(pattern_idx, position)| (&self.patterns[pattern_idx], position))
+    }
+
+    /// Returns the next departures from all platforms of the stop after the time of the date, ordered by the
+    /// predicted departure time. Trips of the previous service day still running after midnight are included,
+    /// trips terminating at the stop and trips of modes not allowed by the filter are skipped
+    pub fn get_departures<'a>(
+        &'a self,
+        stop_platforms: &StopPlatforms,
+        date: NaiveDate,
+        from_time: u32,
+        limit: usize,
+        modes: ModeFilter,
+        delays: &'a DelayOverlay,
+    ) -> Vec<Departure<'a>> {
+        let days = ServiceDay::around(&self.calendar, date);
+        let from_time = self.calendar.search_seconds(date, from_time);
+
+        let mut departures: Vec<Departure> = days
+            .iter()
+            .flat_map(|day| {
+                stop_platforms.platforms
+                    .iter()
+                    .flat_map(|&stop| self.stop_patterns[stop as usize].iter())
+                    .filter(|&&(pattern_idx, _)| modes.allows(self.routes[self.patterns[pattern_idx].route as usize].mode))
+                    .map(move |&(pattern_idx, position)| (day, delays.timetable(self, pattern_idx, day.date), position))
+            })
+            .filter(|(_, timetable, position)| position + 1 < timetable.pattern.stops.len())
+            .flat_map(|(day, timetable, position)| {
+                // Trips come ordered by the departure, so no more than `limit` trips of every pattern are needed
+                timetable.trips_departing_from(position, from_time.saturating_sub(day.offset))
+                    .into_iter()
+                    .filter(|&trip_idx| day.active_services[timetable.pattern.services[trip_idx] as usize])
+                    .take(limit)
+                    .map(move |trip_idx| self.get_departure(timetable, day.date, trip_idx, position))
+            })
+            .collect();
+
+        departures.sort_by_cached_key(|d| self.calendar.timestamp(d.get_predicted_departure_time().unwrap_or(d.get_departure_time())));
+        departures.truncate(limit);
+        departures
+    }
+
+    fn get_departure(&self, timetable: Timetable, date: NaiveDate, trip_idx: usize, position: usize) -> Departure<'_> {
+        let pattern = timetable.pattern;
+        let trip = &self.trips[pattern.trips[trip_idx] as usize];
+        let last_stop = &self.platforms[*pattern.stops.last().unwrap() as usize];
+
+        Departure {
+            trip,
+            route: &self.routes[trip.route as usize],
+            platform: &self.platforms[pattern.stops[position] as usize],
+            service_date: date,
+            departure_time: pattern.departure_at(trip_idx, position),
+            predicted_departure_time: timetable.is_predicted(trip_idx).then(|| timetable.departure_at(trip_idx, position)),
+            headsign: self.stop_headsigns
+                .get(&(pattern.trips[trip_idx], position as u32))
+                .or(trip.headsign.as_ref())
+                .unwrap_or(&last_stop.name),
         }
-    
-        results
     }
-    
 
-    pub fn get_direct_trips(&self, from_stop_id: &str, to_stop_id: &str) -> Option<Vec<Arc<DirectTrip>>> {
-        self.direct_trips.get(&(from_stop_id, to_stop_id)).cloned()
+    pub fn get_direct_trip(&self, timetable: Timetable, date: NaiveDate, trip_idx: usize, from_idx: usize, to_idx: usize) -> DirectTrip<'_> {
+        let pattern = timetable.pattern;
+        let trip = &self.trips[pattern.trips[trip_idx] as usize];
+        let is_predicted = timetable.is_predicted(trip_idx);
+
+        DirectTrip {
+            trip,
+            route: &self.routes[trip.route as usize],
+            stops: pattern.stops[from_idx..to_idx + 1].iter().map(|&stop| &self.platforms[stop as usize]).collect(),
+            service_date: date,
+            departure_time: pattern.departure_at(trip_idx, from_idx),
+            arrival_time: pattern.arrival_at(trip_idx, to_idx),
+            predicted_departure_time: is_predicted.then(|| timetable.departure_at(trip_idx, from_idx)),
+            predicted_arrival_time: is_predicted.then(|| timetable.arrival_at(trip_idx, to_idx)),
+        }
     }
 
-    pub fn get_stop_name_from_id(&self, id: &str) -> Option<&str> {
-        self.platforms
-            .values()
-            .find(|sp| sp.platforms.iter().any(|p| p.id == id))
-            .map(|sp| sp.stop_name.as_str())
-    }
-
-    pub fn find_route(
-        &self,
-        start_platforms: Arc<StopPlatforms>,
-        end_platforms: Arc<StopPlatforms>,
-        start_time_opt: Option<u32>
-    ) -> Option<Vec<Arc<DirectTrip>>> {
-        let start_time = start_time_opt.unwrap_or_else(|| {
-            let current_time = DateTime::<Local>::from(SystemTime::now()).with_timezone(&Local);
-            (current_time.hour() as u32) * 3600 + (current_time.minute() as u32) * 60 + (current_time.second() as u32)
-        });
-        
-        let mut best_arrival_time = u32::MAX;
-        let mut best_route: Option<Vec<Arc<DirectTrip>>> = None;
-    
-        for start_platform in start_platforms.platforms.iter() {
-            for end_platform in end_platforms.platforms.iter(){
-                if let Some(direct_trips) = self.get_direct_trips(start_platform.id.as_str(), end_platform.id.as_str()) {
-                    if let Some(best_trip) = direct_trips.iter().filter(|&trip| trip.get_departure_time() >= start_time)
-                        .min_by_key(|&trip| trip.get_real_arrival_time()) {
-                        if best_trip.get_real_arrival_time() < best_arrival_time {
-                            best_arrival_time = best_trip.get_real_arrival_time();
-                            best_route = Some(vec![best_trip.clone()]);
-                        }
-                    }
-                }
+    pub fn get_stop_name(&self, stop: StopIdx) -> &str {
+        &self.platforms[stop as usize].name
+    }
 
-                let mut possible_transfers = HashMap::new();
-                if let Some(start_trips) = self.stops_graph.get(start_platform.id.as_str()) {
-                    for (intermediate_stop, trips_from_start) in start_trips {
-                        if let Some(trips_to_end) = self.stops_graph.get(intermediate_stop) {
-                            if trips_to_end.contains_key(end_platform.id.as_str()) {
-                                possible_transfers.insert(intermediate_stop, (trips_from_start, trips_to_end.get(end_platform.id.as_str()).unwrap()));
-                            }
-                        }
-                    }
-                }
+    /// Platforms are sorted by their id
+    pub fn get_platform_by_id(&self, id: &str) -> Option<&Platform> {
+        self.platforms.binary_search_by(|p| p.id.as_str().cmp(id)).ok().map(|idx| &self.platforms[idx])
+    }
+
+    /// Routes are sorted by their id
+    pub fn get_route_by_id(&self, id: &str) -> Option<&Route> {
+        self.routes.binary_search_by(|r| r.id.as_str().cmp(id)).ok().map(|idx| &self.routes[idx])
+    }
 
-                for (_transfer_stop, (trips_from_start, trips_to_end)) in possible_transfers {
-                    for trip_to_transfer in trips_from_start {
-                        if trip_to_transfer.get_departure_time() >= start_time {
-                            for trip_from_transfer in trips_to_end {
-                                if trip_from_transfer.get_departure_time() >= trip_to_transfer.get_real_arrival_time() {
-                                    let arrival_time = trip_from_transfer.get_real_arrival_time();
-                                    if arrival_time < best_arrival_time {
-                                        best_arrival_time = arrival_time;
-                                        best_route = Some(vec![trip_to_transfer.clone(), trip_from_transfer.clone()]);
-                                    }
-                                }
-                            }
-                        }
-                    }
+    /// Returns the platforms a journey can start or end at. A stop can be boarded at any of
+    /// its platforms, coordinates at any platform within the walking radius, or if there
+    /// is none, at the closest platform
+    fn get_access(&self, place: &Place) -> Vec<Access> {
+        match *place {
+            Place::Stop(stop_platforms) => stop_platforms.platforms
+                .iter()
+                .map(|&stop| Access { stop, duration: 0, distance: 0.0 })
+                .collect(),
+            Place::Coordinates { longitude, latitude } => {
+                let mut nearby: Vec<(StopIdx, f64)> = self.spatial_index
+                    .platforms_within(longitude, latitude, self.walking.radius)
+                    .collect();
+
+                if nearby.is_empty() {
+                    nearby.extend(self.spatial_index.nearest_platforms(longitude, latitude).take(1));
                 }
-            }
+
+                nearby
+                    .into_iter()
+                    .map(|(stop, distance)| Access { stop, duration: self.walking.duration(distance), distance })
+                    .collect()
+            },
+        }
+    }
+
+    /// Returns the planned journeys, the best one first. Empty when no journey was found
+    pub fn find_route<'a>(
+        &'a self,
+        from: Place,
+        to: Place,
+        date: NaiveDate,
+        time_opt: Option<TimeConstraint>,
+        options: JourneyOptions,
+        delays: &'a DelayOverlay,
+    ) -> Vec<Vec<Leg<'a>>> {
+        let time = time_opt.unwrap_or_else(|| TimeConstraint::DepartAt(self.calendar.seconds_since_midnight()));
+        let days = ServiceDay::around(&self.calendar, date);
+
+        let query = RoutingQuery {
+            sources: self.get_access(&from),
+            targets: self.get_access(&to),
+            days,
+            time: time.map(|time| self.calendar.search_seconds(date, time)),
+            max_transfers: options.max_transfers,
+            modes: options.modes,
+            num_itineraries: options.num_itineraries,
+            sort: options.sort,
+            delays,
+        };
+
+        if options.algorithm == Algorithm::Csa {
+            return csa::find_journey(self, &query).into_iter().collect();
         }
-    
-        best_route
+
+        if let TimeConstraint::DepartBetween(..) = time {
+            return raptor::find_profile(self, &query).into_iter().take(options.num_itineraries).collect();
+        }
+
+        // A single journey with the earliest arrival does not need the multi-criteria search
+        if options.num_itineraries == 1 && options.sort == SortBy::Time {
+            return raptor::find_journey(self, &query).into_iter().collect();
+        }
+
+        mc_raptor::find_journeys(self, &query)
+    }
+
+    /// Returns the number of vehicles changed during the journey. Staying seated while the
+    /// vehicle continues as the next trip of its block is no change
+    pub fn count_transfers(&self, legs: &[Leg]) -> usize {
+        let transit_legs = legs.iter().filter(|leg| matches!(leg, Leg::Transit(_))).count();
+
+        let in_seat = legs.windows(2).filter(|pair| match pair {
+            [Leg::Transit(previous), Leg::Transit(next)] => {
+                let trip_idx = |trip: &DirectTrip| self.trip_indices.get(&trip.trip.id).copied();
+                previous.service_date == next.service_date
+                    && trip_idx(previous).is_some_and(|idx| self.block_successors[idx as usize] == trip_idx(next))
+            },
+            _ => false,
+        }).count();
+
+        transit_legs.saturating_sub(1 + in_seat)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use crate::{model::ModeFilter, realtime::DelayOverlay, test_feed};
+    use super::TransitIndex;
+
+    /// Platforms of the footpaths leaving Alpha
+    fn footpaths_from_alpha(index: &TransitIndex) -> Vec<&str> {
+        let alpha = index.platforms.iter().position(|platform| platform.id == "A").unwrap();
+        let mut to_stops: Vec<&str> = index.outgoing_footpaths[alpha]
+            .iter()
+            .map(|&footpath_idx| index.platforms[index.footpaths[footpath_idx].to_stop as usize].id.as_str())
+            .collect();
+        to_stops.sort();
+        to_stops
+    }
+
+    #[test]
+    fn builds_footpaths_only_from_unrestricted_walking_transfers() {
+        // Near and Next are within the walking radius of Alpha, the other stops are kilometers away
+        let mut stops = String::from("stop_id,stop_name,stop_lat,stop_lon\nA,Alpha,48.1,17.1\nN,Near,48.1002,17.1\nM,Next,48.0998,17.1\n");
+        for (i, stop_id) in ["B", "C", "D", "E", "F", "G", "H", "I", "J"].iter().enumerate() {
+            stops.push_str(&format!("{},Far {},{},17.1\n", stop_id, stop_id, 48.11 + 0.01 * i as f64));
+        }
+
+        let index = test_feed::index(&[
+            ("stops.txt", &stops),
+            ("routes.txt", "route_id,route_short_name,route_type\nR,1,3\n"),
+            ("trips.txt", "route_id,service_id,trip_id\nR,WD,T\n"),
+            ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\nT,08:00:00,08:00:00,A,1\nT,08:05:00,08:05:00,B,2\n"),
+            ("transfers.txt", "from_stop_id,to_stop_id,transfer_type,min_transfer_time,from_route_id,to_route_id,from_trip_id,to_trip_id\n\
+                A,B,0,,,,,\nA,C,1,,,,,\nA,D,2,120,,,,\n\
+                A,N,3,,,,,\nA,M,3,,R,,,\n\
+                A,E,4,,,,,\nA,F,5,,,,,\n\
+                A,G,0,,R,,,\nA,H,0,,,R,,\nA,I,2,120,,,T,\nA,J,1,,,,,T\n"),
+        ]);
+
+        // Types 0 to 2 become footpaths, type 3 removes the walk to Near, but not the one to Next as it only concerns route R
+        assert_eq!(footpaths_from_alpha(&index), ["B", "C", "D", "M"]);
+    }
+
+    #[test]
+    fn lists_the_next_departures_with_the_night_trips_of_the_previous_day() {
+        // L1 leaves the first platform of Alpha late in the evening and after midnight, L2 the second one after midnight
+        let index = test_feed::index(&[
+            ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\nAa,Alpha,48.1,17.1\nAb,Alpha,48.1003,17.1\nB,Beta,48.11,17.1\n"),
+            ("routes.txt", "route_id,route_short_name,route_type\nL1,1,3\nL2,2,3\n"),
+            ("trips.txt", "route_id,service_id,trip_id\nL1,WD,L1_0\nL1,WD,L1_1\nL1,WD,L1_2\nL2,WD,L2_0\nL2,WD,L2_1\n"),
+            ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                L1_0,23:50:00,23:50:00,Aa,1\nL1_0,24:00:00,24:00:00,B,2\n\
+                L1_1,24:10:00,24:10:00,Aa,1\nL1_1,24:20:00,24:20:00,B,2\n\
+                L1_2,24:30:00,24:30:00,Aa,1\nL1_2,24:40:00,24:40:00,B,2\n\
+                L2_0,00:05:00,00:05:00,Ab,1\nL2_0,00:15:00,00:15:00,B,2\n\
+                L2_1,00:20:00,00:20:00,Ab,1\nL2_1,00:30:00,00:30:00,B,2\n"),
+        ]);
+
+        let alpha = index.stations.iter().find(|stop| stop.stop_name == "Alpha").unwrap();
+        let (monday, tuesday) = (NaiveDate::from_ymd_opt(2026, 10, 19).unwrap(), NaiveDate::from_ymd_opt(2026, 10, 20).unwrap());
+        let delays = DelayOverlay::default();
+        let departures = |limit| {
+            index.get_departures(alpha, tuesday, 0, limit, ModeFilter::new(None, None), &delays)
+                .iter()
+                .map(|departure| (departure.trip.id.clone(), departure.service_date, departure.departure_time))
+                .collect::<Vec<_>>()
+        };
+
+        // The trip of Monday leaving at 23:50 is gone, the ones of its night are listed among the trips of Tuesday
+        let expected = [
+            ("L2_0".to_string(), tuesday, 300),
+            ("L1_1".to_string(), monday, 24 * 3600 + 600),
+            ("L2_1".to_string(), tuesday, 1200),
+            ("L1_2".to_string(), monday, 24 * 3600 + 1800),
+        ];
+        assert_eq!(departures(4), expected);
+        assert_eq!(departures(3), expected[..3]);
+    }
+}
@@ -1,11 +1,47 @@
 use std::time::Instant;
+use chrono::NaiveDate;
+use crate::model::Mode;
 
 pub fn format_seconds_to_minutes(seconds: u32) -> String {
     format!("{:02}m", seconds / 60)
 }
 
-pub fn format_u32_time(time: u32) -> String {
-    format!("{:02}:{:02}", time / 3600, (time / 60) % 60)
+/// Latest hour of a time, GTFS times of trips running past midnight go up to the next day
+const MAX_HOURS: u32 = 47;
+
+/// Parses a time in the HH:MM format into seconds since midnight
+pub fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+    if hours > MAX_HOURS || minutes >= 60 {
+        return None;
+    }
+
+    hours.checked_mul(3600)?.checked_add(minutes.checked_mul(60)?)
+}
+
+/// Parses a bounding box in the min_lon,min_lat,max_lon,max_lat format
+pub fn parse_bbox(bbox: &str) -> Option<(f64, f64, f64, f64)> {
+    bbox.split(',').map(|s| s.trim().parse::<f64>().ok()).collect::<Option<Vec<f64>>>().and_then(|parts| {
+        if parts.len() == 4 && parts[0] <= parts[2] && parts[1] <= parts[3] {
+            Some((parts[0], parts[1], parts[2], parts[3]))
+        } else {
+            None
+        }
+    })
+}
+
+/// Parses a comma separated list of modes, e.g. tram,bus,trolleybus
+pub fn parse_modes(modes: &str) -> Option<Vec<Mode>> {
+    modes.split(',').map(|mode| Mode::from_name(mode.trim())).collect()
+}
+
+/// Parses a date in the YYYY-MM-DD format. Searches board the trips of the neighbouring
+/// service days too, so the first and the last representable dates are rejected
+pub fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .filter(|date| date.pred_opt().is_some() && date.succ_opt().is_some())
 }
 
 pub fn measure<F, R>(func: F) -> (R, String)
@@ -18,4 +54,32 @@ where
     let elapsed = format!("{} ms", duration);
 
     (result, elapsed)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use super::{parse_date, parse_time};
+
+    #[test]
+    fn parses_times_past_midnight() {
+        assert_eq!(parse_time("08:05"), Some(8 * 3600 + 5 * 60));
+        assert_eq!(parse_time("24:20"), Some(24 * 3600 + 20 * 60));
+        assert_eq!(parse_time("47:59"), Some(47 * 3600 + 59 * 60));
+    }
+
+    #[test]
+    fn rejects_malformed_times() {
+        for time in ["9999999:00", "48:00", "25:99", "12:75", "12", "12:00:00", "-1:00", ""] {
+            assert_eq!(parse_time(time), None, "{} was accepted", time);
+        }
+    }
+
+    #[test]
+    fn rejects_dates_without_neighbours() {
+        assert_eq!(parse_date("2026-10-19"), NaiveDate::from_ymd_opt(2026, 10, 19));
+        assert_eq!(parse_date(&NaiveDate::MIN.format("%Y-%m-%d").to_string()), None);
+        assert_eq!(parse_date(&NaiveDate::MAX.format("%Y-%m-%d").to_string()), None);
+        assert_eq!(parse_date("2026-02-30"), None);
+    }
+}